@@ -0,0 +1,3 @@
+def helper(n)
+  n + 1
+end
@@ -0,0 +1,2 @@
+require 'lib/answer'
+require_relative 'helper'
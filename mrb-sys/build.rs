@@ -1,5 +1,13 @@
 fn main() {
-    cc::Build::new()
+    let mut build = cc::Build::new();
+
+    // must match the config libmruby was built with, as it changes the
+    // layout of mrb_state
+    if cfg!(feature = "debug-hook") {
+        build.define("MRB_ENABLE_DEBUG_HOOK", None);
+    }
+
+    build
         .file("src/wrapper.c")
         .compile("mruby_rust");
 
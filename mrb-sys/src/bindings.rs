@@ -3008,6 +3008,7 @@ pub struct mrb_state {
     pub ecall_nest: u16,
 }
 #[test]
+#[cfg(not(feature = "debug-hook"))]
 fn bindgen_test_layout_mrb_state() {
     assert_eq!(
         ::std::mem::size_of::<mrb_state>(),
@@ -7947,12 +7948,17 @@ fn bindgen_test_layout_mrb_jmpbuf() {
 pub struct mrbrs_ud {
     pub panic_carrier: *mut RObject,
     pub panic_info: *mut ::std::os::raw::c_void,
+    pub timeout_carrier: *mut RObject,
+    pub instruction_limit_carrier: *mut RObject,
+    pub interrupt_carrier: *mut RObject,
+    pub limits: *mut ::std::os::raw::c_void,
+    pub symbols: *mut ::std::os::raw::c_void,
 }
 #[test]
 fn bindgen_test_layout_mrbrs_ud() {
     assert_eq!(
         ::std::mem::size_of::<mrbrs_ud>(),
-        16usize,
+        56usize,
         concat!("Size of: ", stringify!(mrbrs_ud))
     );
     assert_eq!(
@@ -7980,13 +7986,77 @@ fn bindgen_test_layout_mrbrs_ud() {
             stringify!(panic_info)
         )
     );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<mrbrs_ud>())).timeout_carrier as *const _ as usize },
+        16usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(mrbrs_ud),
+            "::",
+            stringify!(timeout_carrier)
+        )
+    );
+    assert_eq!(
+        unsafe {
+            &(*(::std::ptr::null::<mrbrs_ud>())).instruction_limit_carrier as *const _ as usize
+        },
+        24usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(mrbrs_ud),
+            "::",
+            stringify!(instruction_limit_carrier)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<mrbrs_ud>())).interrupt_carrier as *const _ as usize },
+        32usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(mrbrs_ud),
+            "::",
+            stringify!(interrupt_carrier)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<mrbrs_ud>())).limits as *const _ as usize },
+        40usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(mrbrs_ud),
+            "::",
+            stringify!(limits)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<mrbrs_ud>())).symbols as *const _ as usize },
+        48usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(mrbrs_ud),
+            "::",
+            stringify!(symbols)
+        )
+    );
 }
 extern "C" {
-    pub fn mrbrs_open_core() -> *mut mrb_state;
+    pub fn mrbrs_open_core(
+        allocf: mrb_allocf,
+        allocf_ud: *mut ::std::os::raw::c_void,
+    ) -> *mut mrb_state;
 }
 extern "C" {
     pub fn mrbrs_close(mrb: *mut mrb_state);
 }
+extern "C" {
+    pub fn mrbrs_has_code_fetch_hook() -> bool;
+}
+extern "C" {
+    pub fn mrbrs_get_ud(mrb: *mut mrb_state) -> *mut mrbrs_ud;
+}
+extern "C" {
+    pub fn mrbrs_state_prefix_size() -> size_t;
+}
 extern "C" {
     pub fn mrbrs_gc_arena_save(mrb: *mut mrb_state) -> ::std::os::raw::c_int;
 }
@@ -7996,6 +8066,12 @@ extern "C" {
 extern "C" {
     pub fn mrbrs_obj_value(ptr: *mut ::std::os::raw::c_void) -> mrb_value;
 }
+extern "C" {
+    pub fn mrbrs_class_get(
+        mrb: *mut mrb_state,
+        name: *const ::std::os::raw::c_char,
+    ) -> *mut RClass;
+}
 extern "C" {
     pub fn mrbrs_define_class(
         mrb: *mut mrb_state,
@@ -8010,6 +8086,13 @@ extern "C" {
         out_len: *mut size_t,
     ) -> *const ::std::os::raw::c_char;
 }
+extern "C" {
+    pub fn mrbrs_exc_backtrace(
+        mrb: *mut mrb_state,
+        exc: *mut RObject,
+        out_len: *mut size_t,
+    ) -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn mrbrs_method_make_boxed_func(
         mrb: *mut mrb_state,
@@ -8031,6 +8114,91 @@ extern "C" {
         len: size_t,
     ) -> mrb_value;
 }
+extern "C" {
+    pub fn mrbrs_load_nstring_filename(
+        mrb: *mut mrb_state,
+        s: *const ::std::os::raw::c_char,
+        len: size_t,
+        filename: *const ::std::os::raw::c_char,
+        lineno: u16,
+    ) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_parse(
+        mrb: *mut mrb_state,
+        s: *const ::std::os::raw::c_char,
+        len: size_t,
+        filename: *const ::std::os::raw::c_char,
+    ) -> *mut mrb_parser_state;
+}
+extern "C" {
+    pub fn mrbrs_compile(
+        mrb: *mut mrb_state,
+        parser: *mut mrb_parser_state,
+        out_len: *mut size_t,
+    ) -> *mut u8;
+}
+extern "C" {
+    pub fn mrbrs_load_irep_buf(mrb: *mut mrb_state, buf: *const u8, len: size_t) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_session_new(
+        mrb: *mut mrb_state,
+        filename: *const ::std::os::raw::c_char,
+    ) -> *mut mrbc_context;
+}
+extern "C" {
+    pub fn mrbrs_session_load(
+        mrb: *mut mrb_state,
+        cxt: *mut mrbc_context,
+        s: *const ::std::os::raw::c_char,
+        len: size_t,
+    ) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_session_parse(
+        mrb: *mut mrb_state,
+        session: *mut mrbc_context,
+        s: *const ::std::os::raw::c_char,
+        len: size_t,
+    ) -> *mut mrb_parser_state;
+}
+extern "C" {
+    pub fn mrbrs_bool_value(value: bool) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_nil_value() -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_fixnum_value(value: mrb_int) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_symbol_value(sym: mrb_sym) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_fixnum_limits(out_min: *mut mrb_int, out_max: *mut mrb_int);
+}
+extern "C" {
+    pub fn mrbrs_fixnum_get(value: mrb_value, out: *mut mrb_int) -> bool;
+}
+extern "C" {
+    pub fn mrbrs_float_get(value: mrb_value, out: *mut mrb_float) -> bool;
+}
+extern "C" {
+    pub fn mrbrs_symbol_get(value: mrb_value, out: *mut mrb_sym) -> bool;
+}
+extern "C" {
+    pub fn mrbrs_nil_p(value: mrb_value) -> bool;
+}
+extern "C" {
+    pub fn mrbrs_ptr(value: mrb_value) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn mrbrs_float_value(mrb: *mut mrb_state, value: mrb_float) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_str_ptr(str_: mrb_value, out_len: *mut size_t) -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn mrbrs_str_new(
         mrb: *mut mrb_state,
@@ -8045,6 +8213,43 @@ extern "C" {
         len: size_t,
     ) -> mrb_value;
 }
+extern "C" {
+    pub fn mrbrs_obj_as_string(mrb: *mut mrb_state, obj: mrb_value) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_str_buf_new(mrb: *mut mrb_state, capa: size_t) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_str_cat(
+        mrb: *mut mrb_state,
+        str_: mrb_value,
+        p: *const ::std::os::raw::c_char,
+        len: size_t,
+    );
+}
+extern "C" {
+    pub fn mrbrs_str_append(mrb: *mut mrb_state, str_: mrb_value, other: mrb_value);
+}
+extern "C" {
+    pub fn mrbrs_str_resize(mrb: *mut mrb_state, str_: mrb_value, len: mrb_int);
+}
+extern "C" {
+    pub fn mrbrs_str_substr(
+        mrb: *mut mrb_state,
+        str_: mrb_value,
+        beg: mrb_int,
+        len: mrb_int,
+    ) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_str_dup(mrb: *mut mrb_state, str_: mrb_value) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_obj_freeze(mrb: *mut mrb_state, obj: mrb_value);
+}
+extern "C" {
+    pub fn mrbrs_frozen_p(obj: mrb_value) -> bool;
+}
 extern "C" {
     pub fn mrbrs_intern(
         mrb: *mut mrb_state,
@@ -8065,9 +8270,126 @@ extern "C" {
 extern "C" {
     pub fn mrbrs_hash_set(mrb: *mut mrb_state, hash: mrb_value, key: mrb_value, value: mrb_value);
 }
+extern "C" {
+    pub fn mrbrs_hash_entries(mrb: *mut mrb_state, hash: mrb_value) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_ary_new(mrb: *mut mrb_state) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_ary_push(mrb: *mut mrb_state, ary: mrb_value, value: mrb_value);
+}
+extern "C" {
+    pub fn mrbrs_ary_ptr(ary: mrb_value, out_len: *mut size_t) -> *const mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_define_global_const(
+        mrb: *mut mrb_state,
+        name: *const ::std::os::raw::c_char,
+        value: mrb_value,
+    );
+}
+extern "C" {
+    pub fn mrbrs_gv_get(mrb: *mut mrb_state, name: *const ::std::os::raw::c_char) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_gv_set(
+        mrb: *mut mrb_state,
+        name: *const ::std::os::raw::c_char,
+        value: mrb_value,
+    );
+}
+extern "C" {
+    pub fn mrbrs_exc_new(
+        mrb: *mut mrb_state,
+        klass: *mut RClass,
+        message: *const ::std::os::raw::c_char,
+        len: size_t,
+    ) -> *mut RObject;
+}
+extern "C" {
+    pub fn mrbrs_caller_location(
+        mrb: *mut mrb_state,
+        out_line: *mut i32,
+    ) -> *const ::std::os::raw::c_char;
+}
 extern "C" {
     pub fn mrbrs_equal(mrb: *mut mrb_state, a: mrb_value, b: mrb_value) -> bool;
 }
+extern "C" {
+    pub fn mrbrs_funcall(
+        mrb: *mut mrb_state,
+        self_: mrb_value,
+        mid: mrb_sym,
+        argc: mrb_int,
+        argv: *const mrb_value,
+    ) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_respond_to(mrb: *mut mrb_state, obj: mrb_value, mid: mrb_sym) -> bool;
+}
+extern "C" {
+    pub fn mrbrs_singleton_class(mrb: *mut mrb_state, obj: mrb_value) -> *mut RClass;
+}
+extern "C" {
+    pub fn mrbrs_class_path(mrb: *mut mrb_state, klass: *mut RClass) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_path2class(
+        mrb: *mut mrb_state,
+        path: *const ::std::os::raw::c_char,
+        len: size_t,
+    ) -> *mut RClass;
+}
+extern "C" {
+    pub fn mrbrs_obj_alloc(mrb: *mut mrb_state, klass: *mut RClass) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_obj_ivars(mrb: *mut mrb_state, obj: mrb_value) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_iv_set(mrb: *mut mrb_state, obj: mrb_value, name: mrb_value, value: mrb_value);
+}
+extern "C" {
+    pub fn mrbrs_obj_set_class(mrb: *mut mrb_state, obj: mrb_value, klass: *mut RClass);
+}
+extern "C" {
+    pub fn mrbrs_undef_method(
+        mrb: *mut mrb_state,
+        klass: *mut RClass,
+        name: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn mrbrs_undef_class_method(
+        mrb: *mut mrb_state,
+        klass: *mut RClass,
+        name: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn mrbrs_remove_const(
+        mrb: *mut mrb_state,
+        klass: *mut RClass,
+        name: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn mrbrs_sandbox_block_only(
+        mrb: *mut mrb_state,
+        klass: *mut RClass,
+        name: *const ::std::os::raw::c_char,
+    );
+}
+extern "C" {
+    pub fn mrbrs_sandbox_guard_raise(mrb: *mut mrb_state);
+}
+extern "C" {
+    pub fn mrbrs_sandbox_core_classes(mrb: *mut mrb_state) -> mrb_value;
+}
+extern "C" {
+    pub fn mrbrs_sandbox_protect_classes(mrb: *mut mrb_state, classes: mrb_value);
+}
 pub type __builtin_va_list = [__va_list_tag; 1usize];
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
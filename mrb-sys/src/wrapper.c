@@ -1,8 +1,11 @@
+#include <stddef.h>
 #include <stdlib.h>
 #include <string.h>
 
 #include "wrapper.h"
 
+#include <mruby/debug.h>
+
 #define PROTECT(body, rescue) do { \
         struct mrb_jmpbuf jmp; \
         struct mrb_jmpbuf* prev_jmp = mrb->jmp; \
@@ -16,8 +19,45 @@
         } MRB_END_EXC(&jmp); \
     } while (0)
 
+struct RObject* mrbrs_limits_check(mrb_state*);
+
+#ifdef MRB_ENABLE_DEBUG_HOOK
+static void
+code_fetch_hook(mrb_state* mrb, mrb_irep* irep, const mrb_code* pc, mrb_value* regs)
+{
+    (void)irep;
+    (void)pc;
+    (void)regs;
+
+    struct RObject* carrier = mrbrs_limits_check(mrb);
+
+    if (carrier) {
+        mrb->exc = carrier;
+        MRB_THROW(mrb->jmp);
+    }
+}
+#endif
+
+static mrb_value
+abort_carrier_inspect(mrb_state* mrb, mrb_value self)
+{
+    return mrb_iv_get(mrb, self, mrb_intern_lit(mrb, "message"));
+}
+
+static struct RObject*
+new_abort_carrier(mrb_state* mrb, struct RClass* klass, const char* message)
+{
+    mrb_value obj = mrb_obj_new(mrb, klass, 0, NULL);
+
+    // iv names without a leading @ aren't visible to Ruby code
+    mrb_iv_set(mrb, obj, mrb_intern_lit(mrb, "message"), mrb_str_new_cstr(mrb, message));
+    mrb_gc_register(mrb, obj);
+
+    return mrb_obj_ptr(obj);
+}
+
 mrb_state*
-mrbrs_open_core()
+mrbrs_open_core(mrb_allocf allocf, void* allocf_ud)
 {
     // allocate userdata struct for mrbrs
 
@@ -29,7 +69,7 @@ mrbrs_open_core()
 
     // open mruby
 
-    mrb_state* mrb = mrb_open_core(mrb_default_allocf, NULL);
+    mrb_state* mrb = mrb_open_core(allocf, allocf_ud);
 
     if (!mrb) {
         // free ud if we can't open mruby
@@ -60,6 +100,19 @@ mrbrs_open_core()
 
         ud->panic_carrier = mrb_obj_ptr(ex_panic_obj);
 
+        // execution limit carriers use the same trick so that scripts can't
+        // rescue their way out of a timeout. unlike the panic carrier these
+        // are reported back to the user, so give them a readable inspect
+        mrb_value abort_obj = mrb_obj_dup(mrb, mrb_obj_value(basic_object));
+        mrb_gc_protect(mrb, abort_obj);
+
+        struct RClass* abort_class = mrb_class_ptr(abort_obj);
+        mrb_define_method(mrb, abort_class, "inspect", abort_carrier_inspect, MRB_ARGS_NONE());
+
+        ud->timeout_carrier = new_abort_carrier(mrb, abort_class, "execution timed out");
+        ud->instruction_limit_carrier = new_abort_carrier(mrb, abort_class, "instruction limit exceeded");
+        ud->interrupt_carrier = new_abort_carrier(mrb, abort_class, "execution interrupted");
+
         mrb_gc_arena_restore(mrb, ai);
         mrb->jmp = NULL;
     } MRB_CATCH(&jmp) {
@@ -68,9 +121,41 @@ mrbrs_open_core()
         mrb = NULL;
     } MRB_END_EXC(&jmp);
 
+#ifdef MRB_ENABLE_DEBUG_HOOK
+    if (mrb) {
+        mrb->code_fetch_hook = code_fetch_hook;
+    }
+#endif
+
     return mrb;
 }
 
+bool
+mrbrs_has_code_fetch_hook()
+{
+#ifdef MRB_ENABLE_DEBUG_HOOK
+    return true;
+#else
+    return false;
+#endif
+}
+
+// ud comes after fields which only exist in some mruby build configs (such as
+// the debug hooks), so it's read from here rather than through the bindings
+mrbrs_ud*
+mrbrs_get_ud(mrb_state* mrb)
+{
+    return (mrbrs_ud*)mrb->ud;
+}
+
+// the bindings' layout of mrb_state is only trusted up to kernel_module. this
+// lets Rust check that it agrees with the config mruby was built with
+size_t
+mrbrs_state_prefix_size()
+{
+    return offsetof(mrb_state, kernel_module) + sizeof(struct RClass*);
+}
+
 void
 mrbrs_close(mrb_state* mrb)
 {
@@ -96,6 +181,27 @@ mrbrs_obj_value(void* ptr)
     return mrb_obj_value(ptr);
 }
 
+struct RClass*
+mrbrs_class_get(mrb_state* mrb, const char* name)
+{
+    struct RClass* result = NULL;
+
+    PROTECT({
+        mrb_value object = mrb_obj_value(mrb->object_class);
+        mrb_sym sym = mrb_intern_cstr(mrb, name);
+
+        if (mrb_const_defined(mrb, object, sym)) {
+            mrb_value klass = mrb_const_get(mrb, object, sym);
+
+            if (mrb_type(klass) == MRB_TT_CLASS || mrb_type(klass) == MRB_TT_MODULE) {
+                result = mrb_class_ptr(klass);
+            }
+        }
+    }, {});
+
+    return result;
+}
+
 struct RClass*
 mrbrs_define_class(mrb_state* mrb, const char* name, struct RClass* superclass)
 {
@@ -154,6 +260,32 @@ mrbrs_inspect(mrb_state* mrb, mrb_value obj, size_t* out_len)
 }
 
 
+const char*
+mrbrs_exc_backtrace(mrb_state* mrb, struct RObject* exc, size_t* out_len)
+{
+    const char* result = NULL;
+    *out_len = 0;
+
+    // like inspect, this is used while reporting errors so it must not raise
+    PROTECT({
+        mrb_value backtrace = mrb_exc_backtrace(mrb, mrb_obj_value(exc));
+
+        if (mrb_array_p(backtrace) && RARRAY_LEN(backtrace) > 0) {
+            mrb_value lines = mrb_ary_join(mrb, backtrace, mrb_str_new_lit(mrb, "\n"));
+            mrb_gc_protect(mrb, lines);
+            mrb_obj_freeze(mrb, lines);
+
+            result = RSTRING_PTR(lines);
+            *out_len = RSTRING_LEN(lines);
+        }
+    }, {
+        mrb->exc = NULL;
+    });
+
+    return result;
+}
+
+
 void mrbrs_method_free_boxed_func(mrb_state*, void*);
 void mrbrs_method_dispatch_boxed_func(mrb_state*, mrb_value, void*, mrb_value*);
 
@@ -238,81 +370,1007 @@ mrbrs_load_nstring(mrb_state* mrb, const char* s, size_t len)
 }
 
 mrb_value
-mrbrs_str_new(mrb_state* mrb, const char* p, size_t len)
+mrbrs_load_nstring_filename(mrb_state* mrb, const char* s, size_t len, const char* filename, uint16_t lineno)
 {
     mrb_value result = mrb_nil_value();
+    mrbc_context* volatile cxt = NULL;
+
+    int ai = mrb_gc_arena_save(mrb);
 
     PROTECT({
-        result = mrb_str_new(mrb, p, len);
+        cxt = mrbc_context_new(mrb);
+        cxt->lineno = lineno;
+        mrbc_filename(mrb, cxt, filename);
+
+        result = mrb_load_nstring_cxt(mrb, s, len, cxt);
+        mrb_gc_arena_restore(mrb, ai);
+        mrb_gc_protect(mrb, result);
+    }, {
+        mrb_gc_arena_restore(mrb, ai);
+    });
+
+    if (cxt) {
+        mrbc_context_free(mrb, cxt);
+    }
+
+    return result;
+}
+
+struct mrb_parser_state*
+mrbrs_parse(mrb_state* mrb, const char* s, size_t len, const char* filename)
+{
+    struct mrb_parser_state* result = NULL;
+    mrbc_context* volatile cxt = NULL;
+
+    PROTECT({
+        cxt = mrbc_context_new(mrb);
+        cxt->capture_errors = TRUE;
+        mrbc_filename(mrb, cxt, filename);
+
+        // the parser only holds on to the context if it has a partial hook,
+        // so it is safe to free it as soon as we're done parsing
+        result = mrb_parse_nstring(mrb, s, len, cxt);
+
+        if (!result) {
+            mrb_exc_raise(mrb, mrb_obj_value(mrb->nomem_err));
+        }
+    }, {});
+
+    if (cxt) {
+        mrbc_context_free(mrb, cxt);
+    }
+
+    return result;
+}
+
+uint8_t*
+mrbrs_compile(mrb_state* mrb, struct mrb_parser_state* parser, size_t* out_len)
+{
+    uint8_t* result = NULL;
+
+    PROTECT({
+        struct RProc* proc = mrb_generate_code(mrb, parser);
+
+        if (!proc) {
+            mrb_raise(mrb, E_SCRIPT_ERROR, "codegen error");
+        }
+
+        // keep debug info so that backtraces from loaded bytecode still
+        // point at the original source
+        if (mrb_dump_irep(mrb, proc->body.irep, DUMP_DEBUG_INFO, &result, out_len) != MRB_DUMP_OK) {
+            mrb_raise(mrb, E_SCRIPT_ERROR, "could not dump bytecode");
+        }
     }, {});
 
     return result;
 }
 
+static void
+check_rite_header(mrb_state* mrb, const uint8_t* buf, size_t len)
+{
+    // mruby validates all of this itself, but only reports "irep load error"
+    // when something is wrong. check up front so we can say what it was
+    const struct rite_binary_header* header = (const struct rite_binary_header*)buf;
+
+    if (len < sizeof(*header)) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "bytecode is truncated");
+    }
+
+    if (memcmp(header->binary_ident, RITE_BINARY_IDENT, sizeof(header->binary_ident)) != 0) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "not mruby bytecode");
+    }
+
+    if (memcmp(header->binary_version, RITE_BINARY_FORMAT_VER, sizeof(header->binary_version)) != 0) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "incompatible bytecode version, expected " RITE_BINARY_FORMAT_VER);
+    }
+
+    size_t size = bin_to_uint32(header->binary_size);
+
+    if (size < sizeof(*header)) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "bytecode header is corrupt");
+    }
+
+    if (len < size) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "bytecode is truncated");
+    }
+
+    size_t crc_offset = offsetof(struct rite_binary_header, binary_crc) + sizeof(header->binary_crc);
+
+    if (calc_crc_16_ccitt(buf + crc_offset, size - crc_offset, 0) != bin_to_uint16(header->binary_crc)) {
+        mrb_raise(mrb, E_SCRIPT_ERROR, "bytecode checksum mismatch");
+    }
+}
+
 mrb_value
-mrbrs_str_new_static(mrb_state* mrb, const char* p, size_t len)
+mrbrs_load_irep_buf(mrb_state* mrb, const uint8_t* buf, size_t len)
 {
     mrb_value result = mrb_nil_value();
 
+    int ai = mrb_gc_arena_save(mrb);
+
     PROTECT({
-        result = mrb_str_new_static(mrb, p, len);
+        check_rite_header(mrb, buf, len);
+        result = mrb_load_irep_buf(mrb, buf, len);
+        mrb_gc_arena_restore(mrb, ai);
+        mrb_gc_protect(mrb, result);
+    }, {
+        mrb_gc_arena_restore(mrb, ai);
+    });
+
+    return result;
+}
+
+mrbc_context*
+mrbrs_session_new(mrb_state* mrb, const char* filename)
+{
+    mrbc_context* volatile cxt = NULL;
+
+    PROTECT({
+        cxt = mrbc_context_new(mrb);
+        cxt->capture_errors = TRUE;
+        cxt->keep_lv = TRUE;
+        mrbc_filename(mrb, cxt, filename);
+    }, {
+        if (cxt) {
+            mrbc_context_free(mrb, cxt);
+            cxt = NULL;
+        }
+    });
+
+    return cxt;
+}
+
+mrb_value
+mrbrs_session_load(mrb_state* mrb, mrbc_context* cxt, const char* s, size_t len)
+{
+    mrb_value result = mrb_nil_value();
+
+    int ai = mrb_gc_arena_save(mrb);
+
+    PROTECT({
+        result = mrb_load_nstring_cxt(mrb, s, len, cxt);
+        mrb_gc_arena_restore(mrb, ai);
+        mrb_gc_protect(mrb, result);
+    }, {
+        mrb_gc_arena_restore(mrb, ai);
+    });
+
+    return result;
+}
+
+struct mrb_parser_state*
+mrbrs_session_parse(mrb_state* mrb, mrbc_context* session, const char* s, size_t len)
+{
+    struct mrb_parser_state* result = NULL;
+    mrbc_context* volatile cxt = NULL;
+
+    PROTECT({
+        // the parser records any new local variables in the context it is
+        // given, so parse against a copy to leave the session untouched
+        cxt = mrbc_context_new(mrb);
+        cxt->capture_errors = TRUE;
+
+        if (session->filename) {
+            mrbc_filename(mrb, cxt, session->filename);
+        }
+
+        if (session->slen > 0) {
+            cxt->syms = mrb_malloc(mrb, sizeof(mrb_sym) * session->slen);
+            memcpy(cxt->syms, session->syms, sizeof(mrb_sym) * session->slen);
+            cxt->slen = session->slen;
+        }
+
+        result = mrb_parse_nstring(mrb, s, len, cxt);
+
+        if (!result) {
+            mrb_exc_raise(mrb, mrb_obj_value(mrb->nomem_err));
+        }
     }, {});
 
+    if (cxt) {
+        mrbc_context_free(mrb, cxt);
+    }
+
     return result;
 }
 
 mrb_value
-mrbrs_intern(mrb_state* mrb, const char* p, size_t len)
+mrbrs_bool_value(bool value)
+{
+    return mrb_bool_value(value);
+}
+
+mrb_value
+mrbrs_nil_value(void)
+{
+    return mrb_nil_value();
+}
+
+mrb_value
+mrbrs_fixnum_value(mrb_int value)
+{
+    return mrb_fixnum_value(value);
+}
+
+mrb_value
+mrbrs_symbol_value(mrb_sym sym)
+{
+    return mrb_symbol_value(sym);
+}
+
+void
+mrbrs_fixnum_limits(mrb_int* out_min, mrb_int* out_max)
+{
+    // with word boxing Fixnums give up bits to the type tag, which
+    // MRB_INT_MIN and MRB_INT_MAX account for
+    *out_min = MRB_INT_MIN;
+    *out_max = MRB_INT_MAX;
+}
+
+bool
+mrbrs_fixnum_get(mrb_value value, mrb_int* out)
+{
+    if (!mrb_fixnum_p(value)) {
+        return false;
+    }
+
+    *out = mrb_fixnum(value);
+    return true;
+}
+
+bool
+mrbrs_float_get(mrb_value value, mrb_float* out)
+{
+    if (!mrb_float_p(value)) {
+        return false;
+    }
+
+    *out = mrb_float(value);
+    return true;
+}
+
+bool
+mrbrs_symbol_get(mrb_value value, mrb_sym* out)
+{
+    if (!mrb_symbol_p(value)) {
+        return false;
+    }
+
+    *out = mrb_symbol(value);
+    return true;
+}
+
+bool
+mrbrs_nil_p(mrb_value value)
+{
+    return mrb_nil_p(value);
+}
+
+void*
+mrbrs_ptr(mrb_value value)
+{
+    return mrb_ptr(value);
+}
+
+mrb_value
+mrbrs_float_value(mrb_state* mrb, mrb_float value)
 {
     mrb_value result = mrb_nil_value();
 
     PROTECT({
-        result = mrb_symbol_value(mrb_intern(mrb, p, len));
+        result = mrb_float_value(mrb, value);
     }, {});
 
     return result;
 }
 
+const char*
+mrbrs_str_ptr(mrb_value str, size_t* out_len)
+{
+    if (!mrb_string_p(str)) {
+        return NULL;
+    }
+
+    *out_len = RSTRING_LEN(str);
+    return RSTRING_PTR(str);
+}
+
 mrb_value
-mrbrs_intern_static(mrb_state* mrb, const char* p, size_t len)
+mrbrs_str_new(mrb_state* mrb, const char* p, size_t len)
 {
     mrb_value result = mrb_nil_value();
 
     PROTECT({
-        result = mrb_symbol_value(mrb_intern_static(mrb, p, len));
+        result = mrb_str_new(mrb, p, len);
     }, {});
 
     return result;
 }
 
 mrb_value
-mrbrs_hash_new(mrb_state* mrb)
+mrbrs_str_new_static(mrb_state* mrb, const char* p, size_t len)
 {
     mrb_value result = mrb_nil_value();
 
     PROTECT({
-        result = mrb_hash_new(mrb);
+        result = mrb_str_new_static(mrb, p, len);
     }, {});
 
     return result;
 }
 
-void
-mrbrs_hash_set(mrb_state* mrb, mrb_value hash, mrb_value key, mrb_value value)
+mrb_value
+mrbrs_obj_as_string(mrb_state* mrb, mrb_value obj)
 {
+    mrb_value result = mrb_nil_value();
+
     PROTECT({
-        mrb_hash_set(mrb, hash, key, value);
+        result = mrb_obj_as_string(mrb, obj);
     }, {});
+
+    return result;
 }
 
-bool
-mrbrs_equal(mrb_state* mrb, mrb_value a, mrb_value b)
+mrb_value
+mrbrs_str_buf_new(mrb_state* mrb, size_t capa)
 {
-    bool result = false;
+    mrb_value result = mrb_nil_value();
 
     PROTECT({
-        result = mrb_equal(mrb, a, b);
+        result = mrb_str_buf_new(mrb, capa);
     }, {});
 
     return result;
 }
+
+// raises unless str is a String which may be modified. frozen Strings raise
+// FrozenError, and Strings sharing a buffer, such as those made with
+// mrb_str_new_static, get a buffer of their own so the original is never
+// written to
+static void
+str_modifiable(mrb_state* mrb, mrb_value str)
+{
+    if (!mrb_string_p(str)) {
+        mrb_raise(mrb, E_TYPE_ERROR, "expected String");
+    }
+
+    mrb_str_modify(mrb, mrb_str_ptr(str));
+}
+
+void
+mrbrs_str_cat(mrb_state* mrb, mrb_value str, const char* p, size_t len)
+{
+    PROTECT({
+        str_modifiable(mrb, str);
+        mrb_str_cat(mrb, str, p, len);
+    }, {});
+}
+
+void
+mrbrs_str_append(mrb_state* mrb, mrb_value str, mrb_value other)
+{
+    PROTECT({
+        str_modifiable(mrb, str);
+
+        if (!mrb_string_p(other)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected String");
+        }
+
+        mrb_str_append(mrb, str, other);
+    }, {});
+}
+
+void
+mrbrs_str_resize(mrb_state* mrb, mrb_value str, mrb_int len)
+{
+    PROTECT({
+        mrb_int old_len;
+
+        str_modifiable(mrb, str);
+        old_len = RSTRING_LEN(str);
+        mrb_str_resize(mrb, str, len);
+
+        // mrb_str_resize leaves any new bytes uninitialized
+        if (len > old_len) {
+            memset(RSTRING_PTR(str) + old_len, 0, len - old_len);
+        }
+    }, {});
+}
+
+mrb_value
+mrbrs_str_substr(mrb_state* mrb, mrb_value str, mrb_int beg, mrb_int len)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        if (!mrb_string_p(str)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected String");
+        }
+
+        result = mrb_str_substr(mrb, str, beg, len);
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_str_dup(mrb_state* mrb, mrb_value str)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        if (!mrb_string_p(str)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected String");
+        }
+
+        result = mrb_str_dup(mrb, str);
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_obj_freeze(mrb_state* mrb, mrb_value obj)
+{
+    PROTECT({
+        mrb_obj_freeze(mrb, obj);
+    }, {});
+}
+
+bool
+mrbrs_frozen_p(mrb_value obj)
+{
+    // immediates such as nil, numbers and Symbols can never be modified
+    return mrb_immediate_p(obj) || MRB_FROZEN_P(mrb_basic_ptr(obj));
+}
+
+mrb_value
+mrbrs_intern(mrb_state* mrb, const char* p, size_t len)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_symbol_value(mrb_intern(mrb, p, len));
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_intern_static(mrb_state* mrb, const char* p, size_t len)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_symbol_value(mrb_intern_static(mrb, p, len));
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_hash_new(mrb_state* mrb)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_hash_new(mrb);
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_hash_set(mrb_state* mrb, mrb_value hash, mrb_value key, mrb_value value)
+{
+    PROTECT({
+        mrb_hash_set(mrb, hash, key, value);
+    }, {});
+}
+
+mrb_value
+mrbrs_hash_entries(mrb_state* mrb, mrb_value hash)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        mrb_value keys, values;
+        mrb_int i;
+
+        if (!mrb_hash_p(hash)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected Hash");
+        }
+
+        // keys and values come out in the same order, so can be zipped
+        keys = mrb_hash_keys(mrb, hash);
+        values = mrb_hash_values(mrb, hash);
+        result = mrb_ary_new_capa(mrb, RARRAY_LEN(keys) * 2);
+
+        for (i = 0; i < RARRAY_LEN(keys); i++) {
+            mrb_ary_push(mrb, result, RARRAY_PTR(keys)[i]);
+            mrb_ary_push(mrb, result, RARRAY_PTR(values)[i]);
+        }
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_ary_new(mrb_state* mrb)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_ary_new(mrb);
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_ary_push(mrb_state* mrb, mrb_value ary, mrb_value value)
+{
+    PROTECT({
+        if (!mrb_array_p(ary)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected Array");
+        }
+
+        mrb_ary_push(mrb, ary, value);
+    }, {});
+}
+
+const mrb_value*
+mrbrs_ary_ptr(mrb_value ary, size_t* out_len)
+{
+    if (!mrb_array_p(ary)) {
+        return NULL;
+    }
+
+    *out_len = RARRAY_LEN(ary);
+    return RARRAY_PTR(ary);
+}
+
+void
+mrbrs_define_global_const(mrb_state* mrb, const char* name, mrb_value value)
+{
+    PROTECT({
+        mrb_define_global_const(mrb, name, value);
+    }, {});
+}
+
+mrb_value
+mrbrs_gv_get(mrb_state* mrb, const char* name)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_gv_get(mrb, mrb_intern_cstr(mrb, name));
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_gv_set(mrb_state* mrb, const char* name, mrb_value value)
+{
+    PROTECT({
+        mrb_gv_set(mrb, mrb_intern_cstr(mrb, name), value);
+    }, {});
+}
+
+struct RObject*
+mrbrs_exc_new(mrb_state* mrb, struct RClass* klass, const char* message, size_t len)
+{
+    struct RObject* result = NULL;
+
+    PROTECT({
+        result = mrb_obj_ptr(mrb_exc_new(mrb, klass, message, len));
+    }, {});
+
+    return result;
+}
+
+const char*
+mrbrs_caller_location(mrb_state* mrb, int32_t* out_line)
+{
+    mrb_callinfo* ci;
+
+    // walk out from the innermost frame to the first one running Ruby code
+    // with debug info. each frame's return address is kept by its callee
+    for (ci = mrb->c->ci; ci >= mrb->c->cibase; ci--) {
+        if (!ci->proc || MRB_PROC_CFUNC_P(ci->proc) || !ci->proc->body.irep) {
+            continue;
+        }
+
+        mrb_irep* irep = ci->proc->body.irep;
+        ptrdiff_t pc = 0;
+
+        if (ci < mrb->c->ci && ci[1].pc) {
+            pc = ci[1].pc - irep->iseq - 1;
+        }
+
+        if (pc < 0) {
+            pc = 0;
+        }
+
+        const char* filename = mrb_debug_get_filename(mrb, irep, pc);
+
+        if (filename) {
+            *out_line = mrb_debug_get_line(mrb, irep, pc);
+            return filename;
+        }
+    }
+
+    return NULL;
+}
+
+bool
+mrbrs_equal(mrb_state* mrb, mrb_value a, mrb_value b)
+{
+    bool result = false;
+
+    PROTECT({
+        result = mrb_equal(mrb, a, b);
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_funcall(mrb_state* mrb, mrb_value self, mrb_sym mid, mrb_int argc, const mrb_value* argv)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_funcall_argv(mrb, self, mid, argc, argv);
+    }, {});
+
+    return result;
+}
+
+bool
+mrbrs_respond_to(mrb_state* mrb, mrb_value obj, mrb_sym mid)
+{
+    bool result = false;
+
+    PROTECT({
+        result = mrb_respond_to(mrb, obj, mid);
+    }, {});
+
+    return result;
+}
+
+struct RClass*
+mrbrs_singleton_class(mrb_state* mrb, mrb_value obj)
+{
+    struct RClass* result = NULL;
+
+    PROTECT({
+        result = mrb_class_ptr(mrb_singleton_class(mrb, obj));
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_class_path(mrb_state* mrb, struct RClass* klass)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        result = mrb_class_path(mrb, klass);
+    }, {});
+
+    return result;
+}
+
+struct RClass*
+mrbrs_path2class(mrb_state* mrb, const char* path, size_t len)
+{
+    struct RClass* result = NULL;
+
+    PROTECT({
+        struct RClass* klass = mrb->object_class;
+        const char* begin = path;
+        const char* end = path + len;
+
+        while (begin < end) {
+            const char* sep = begin;
+            mrb_sym id;
+            mrb_value value;
+
+            while (sep < end && !(sep + 1 < end && sep[0] == ':' && sep[1] == ':')) {
+                sep++;
+            }
+
+            id = mrb_intern(mrb, begin, sep - begin);
+
+            if (!mrb_const_defined_at(mrb, mrb_obj_value(klass), id)) {
+                mrb_raisef(mrb, E_ARGUMENT_ERROR, "undefined class/module %S", mrb_str_new(mrb, path, len));
+            }
+
+            value = mrb_const_get(mrb, mrb_obj_value(klass), id);
+
+            if (mrb_type(value) != MRB_TT_CLASS && mrb_type(value) != MRB_TT_MODULE) {
+                mrb_raisef(mrb, E_TYPE_ERROR, "%S does not refer to class/module", mrb_str_new(mrb, path, len));
+            }
+
+            klass = mrb_class_ptr(value);
+            begin = sep + 2;
+        }
+
+        result = klass;
+    }, {});
+
+    return result;
+}
+
+mrb_value
+mrbrs_obj_alloc(mrb_state* mrb, struct RClass* klass)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        enum mrb_vtype tt = MRB_INSTANCE_TT(klass);
+
+        // classes defined in Ruby leave their instance type unset
+        if (tt == 0) {
+            tt = MRB_TT_OBJECT;
+        }
+
+        if (klass->tt != MRB_TT_CLASS || tt != MRB_TT_OBJECT) {
+            mrb_raisef(mrb, E_TYPE_ERROR, "can't allocate an instance of %S", mrb_obj_value(klass));
+        }
+
+        // like Marshal, this skips initialize
+        result = mrb_obj_value(mrb_obj_alloc(mrb, MRB_TT_OBJECT, klass));
+    }, {});
+
+    return result;
+}
+
+static int
+collect_ivar(mrb_state* mrb, mrb_sym sym, mrb_value value, void* p)
+{
+    mrb_value ivars = *(mrb_value*)p;
+    mrb_int len;
+    const char* name = mrb_sym_name_len(mrb, sym, &len);
+
+    // names without a leading @ are internal to mruby or this crate
+    if (len > 1 && name[0] == '@') {
+        mrb_ary_push(mrb, ivars, mrb_symbol_value(sym));
+        mrb_ary_push(mrb, ivars, value);
+    }
+
+    return 0;
+}
+
+mrb_value
+mrbrs_obj_ivars(mrb_state* mrb, mrb_value obj)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        mrb_value ivars = mrb_ary_new(mrb);
+        mrb_iv_foreach(mrb, obj, collect_ivar, &ivars);
+        result = ivars;
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_iv_set(mrb_state* mrb, mrb_value obj, mrb_value name, mrb_value value)
+{
+    PROTECT({
+        if (!mrb_symbol_p(name)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected Symbol");
+        }
+
+        // names come from untrusted input when loading a dump
+        mrb_iv_name_sym_check(mrb, mrb_symbol(name));
+        mrb_iv_set(mrb, obj, mrb_symbol(name), value);
+    }, {});
+}
+
+void
+mrbrs_obj_set_class(mrb_state* mrb, mrb_value obj, struct RClass* klass)
+{
+    PROTECT({
+        if (!mrb_string_p(obj) && !mrb_array_p(obj) && !mrb_hash_p(obj)) {
+            mrb_raise(mrb, E_TYPE_ERROR, "expected String, Array or Hash");
+        }
+
+        struct RClass* base = mrb_obj_class(mrb, obj);
+        struct RClass* c = klass;
+
+        while (c && c != base) {
+            c = c->super;
+        }
+
+        if (klass->tt != MRB_TT_CLASS || !c) {
+            mrb_raisef(mrb, E_ARGUMENT_ERROR, "%S is not a subclass of %S", mrb_obj_value(klass), mrb_obj_value(base));
+        }
+
+        mrb_basic_ptr(obj)->c = klass;
+        mrb_field_write_barrier(mrb, mrb_basic_ptr(obj), (struct RBasic*)klass);
+    }, {});
+}
+
+void
+mrbrs_undef_method(mrb_state* mrb, struct RClass* klass, const char* name)
+{
+    PROTECT({
+        mrb_undef_method(mrb, klass, name);
+    }, {});
+}
+
+void
+mrbrs_undef_class_method(mrb_state* mrb, struct RClass* klass, const char* name)
+{
+    PROTECT({
+        mrb_undef_class_method(mrb, klass, name);
+    }, {});
+}
+
+void
+mrbrs_remove_const(mrb_state* mrb, struct RClass* klass, const char* name)
+{
+    PROTECT({
+        mrb_const_remove(mrb, mrb_obj_value(klass), mrb_intern_cstr(mrb, name));
+    }, {});
+}
+
+// sandbox guards wrap an existing method implemented as a plain C function.
+// the original function is stashed in the guard's env and called directly,
+// which works because it reads its arguments from the same call frame
+
+static mrb_value
+sandbox_call_original(mrb_state* mrb, mrb_value self)
+{
+    mrb_func_t func = (mrb_func_t)mrb_cptr(mrb_proc_cfunc_env_get(mrb, 0));
+    return func(mrb, self);
+}
+
+static mrb_value
+sandbox_block_only(mrb_state* mrb, mrb_value self)
+{
+    if (mrb_get_argc(mrb) > 0) {
+        mrb_raise(mrb, mrb_class_get(mrb, "SecurityError"), "evaluating strings is not allowed");
+    }
+
+    return sandbox_call_original(mrb, self);
+}
+
+static mrb_value
+sandbox_protect_classes(mrb_state* mrb, mrb_value self)
+{
+    mrb_value protected_classes = mrb_proc_cfunc_env_get(mrb, 1);
+
+    if (mrb_hash_key_p(mrb, protected_classes, self)) {
+        mrb_raise(mrb, mrb_class_get(mrb, "SecurityError"), "can't define methods on core classes");
+    }
+
+    return sandbox_call_original(mrb, self);
+}
+
+static mrb_value
+sandbox_guard_raise(mrb_state* mrb, mrb_value self)
+{
+    mrbrs_ud* ud = (mrbrs_ud*)mrb->ud;
+
+    struct RObject* carriers[] = {
+        ud->panic_carrier,
+        ud->timeout_carrier,
+        ud->instruction_limit_carrier,
+        ud->interrupt_carrier,
+    };
+
+    if (mrb_get_argc(mrb) > 0) {
+        mrb_value exc = mrb_get_argv(mrb)[0];
+
+        for (size_t i = 0; i < sizeof(carriers) / sizeof(carriers[0]); i++) {
+            mrb_value carrier = mrb_obj_value(carriers[i]);
+            mrb_value carrier_class = mrb_obj_value(mrb_obj_class(mrb, carrier));
+
+            if (mrb_obj_eq(mrb, exc, carrier) || mrb_obj_eq(mrb, exc, carrier_class)) {
+                mrb_raise(mrb, mrb_class_get(mrb, "SecurityError"), "can't raise internal exceptions");
+            }
+        }
+    }
+
+    return sandbox_call_original(mrb, self);
+}
+
+static void
+sandbox_wrap_method(mrb_state* mrb, struct RClass* klass, mrb_sym mid, mrb_func_t guard, mrb_value data)
+{
+    struct RClass* owner = klass;
+    mrb_method_t m = mrb_method_search_vm(mrb, &owner, mid);
+
+    if (MRB_METHOD_UNDEF_P(m)) {
+        return;
+    }
+
+    if (!MRB_METHOD_FUNC_P(m)) {
+        // we only know how to wrap plain C functions, so anything else is
+        // removed outright rather than left unguarded
+        mrb_undef_method_id(mrb, klass, mid);
+        return;
+    }
+
+    mrb_value env[] = {
+        mrb_cptr_value(mrb, (void*)MRB_METHOD_FUNC(m)),
+        data,
+    };
+
+    struct RProc* proc = mrb_proc_new_cfunc_with_env(mrb, guard, 2, env);
+
+    mrb_method_t wrapped;
+    MRB_METHOD_FROM_PROC(wrapped, proc);
+    mrb_define_method_raw(mrb, klass, mid, wrapped);
+}
+
+void
+mrbrs_sandbox_block_only(mrb_state* mrb, struct RClass* klass, const char* name)
+{
+    PROTECT({
+        mrb_sym mid = mrb_intern_cstr(mrb, name);
+        sandbox_wrap_method(mrb, klass, mid, sandbox_block_only, mrb_nil_value());
+    }, {});
+}
+
+void
+mrbrs_sandbox_guard_raise(mrb_state* mrb)
+{
+    PROTECT({
+        mrb_sym mid = mrb_intern_lit(mrb, "raise");
+        struct RClass* kernel_singleton = mrb_class_ptr(mrb_singleton_class(mrb, mrb_obj_value(mrb->kernel_module)));
+
+        sandbox_wrap_method(mrb, mrb->kernel_module, mid, sandbox_guard_raise, mrb_nil_value());
+        sandbox_wrap_method(mrb, kernel_singleton, mid, sandbox_guard_raise, mrb_nil_value());
+    }, {});
+}
+
+static int
+collect_core_class(mrb_state* mrb, mrb_sym sym, mrb_value value, void* p)
+{
+    mrb_value classes = *(mrb_value*)p;
+    (void)sym;
+
+    switch (mrb_type(value)) {
+    case MRB_TT_CLASS:
+    case MRB_TT_MODULE:
+        mrb_hash_set(mrb, classes, value, mrb_true_value());
+        break;
+    default:
+        break;
+    }
+
+    return 0;
+}
+
+// walks Object's constant table directly, as Module#constants comes from
+// mruby-metaprog which isn't part of the core library
+mrb_value
+mrbrs_sandbox_core_classes(mrb_state* mrb)
+{
+    mrb_value result = mrb_nil_value();
+
+    PROTECT({
+        mrb_value classes = mrb_hash_new(mrb);
+        mrb_iv_foreach(mrb, mrb_obj_value(mrb->object_class), collect_core_class, &classes);
+        result = classes;
+    }, {});
+
+    return result;
+}
+
+void
+mrbrs_sandbox_protect_classes(mrb_state* mrb, mrb_value classes)
+{
+    PROTECT({
+        mrb_sym mid = mrb_intern_lit(mrb, "define_method");
+        sandbox_wrap_method(mrb, mrb->module_class, mid, sandbox_protect_classes, classes);
+    }, {});
+}
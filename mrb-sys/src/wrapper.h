@@ -1,26 +1,44 @@
 #include <stdbool.h>
 
 #include <mruby.h>
+#include <mruby/array.h>
 #include <mruby/class.h>
+#include <mruby/compile.h>
 #include <mruby/data.h>
+#include <mruby/dump.h>
 #include <mruby/error.h>
 #include <mruby/hash.h>
 #include <mruby/proc.h>
 #include <mruby/string.h>
 #include <mruby/throw.h>
 #include <mruby/value.h>
+#include <mruby/variable.h>
 
 typedef struct {
     struct RObject* panic_carrier;
     void* panic_info;
+    struct RObject* timeout_carrier;
+    struct RObject* instruction_limit_carrier;
+    struct RObject* interrupt_carrier;
+    void* limits;
+    void* symbols;
 } mrbrs_ud;
 
 mrb_state*
-mrbrs_open_core();
+mrbrs_open_core(mrb_allocf allocf, void* allocf_ud);
 
 void
 mrbrs_close(mrb_state* mrb);
 
+bool
+mrbrs_has_code_fetch_hook();
+
+mrbrs_ud*
+mrbrs_get_ud(mrb_state* mrb);
+
+size_t
+mrbrs_state_prefix_size();
+
 int
 mrbrs_gc_arena_save(mrb_state *mrb);
 
@@ -30,12 +48,18 @@ mrbrs_gc_arena_restore(mrb_state *mrb, int idx);
 mrb_value
 mrbrs_obj_value(void* ptr);
 
+struct RClass*
+mrbrs_class_get(mrb_state* mrb, const char* name);
+
 struct RClass*
 mrbrs_define_class(mrb_state* mrb, const char* name, struct RClass* superclass);
 
 const char*
 mrbrs_inspect(mrb_state* mrb, mrb_value obj, size_t* out_len);
 
+const char*
+mrbrs_exc_backtrace(mrb_state* mrb, struct RObject* exc, size_t* out_len);
+
 struct RProc*
 mrbrs_method_make_boxed_func(mrb_state* mrb, void* boxed_func);
 
@@ -45,12 +69,96 @@ mrbrs_define_method_proc(mrb_state* mrb, struct RClass* klass, const char* name,
 mrb_value
 mrbrs_load_nstring(mrb_state* mrb, const char* s, size_t len);
 
+mrb_value
+mrbrs_load_nstring_filename(mrb_state* mrb, const char* s, size_t len, const char* filename, uint16_t lineno);
+
+struct mrb_parser_state*
+mrbrs_parse(mrb_state* mrb, const char* s, size_t len, const char* filename);
+
+uint8_t*
+mrbrs_compile(mrb_state* mrb, struct mrb_parser_state* parser, size_t* out_len);
+
+mrb_value
+mrbrs_load_irep_buf(mrb_state* mrb, const uint8_t* buf, size_t len);
+
+mrbc_context*
+mrbrs_session_new(mrb_state* mrb, const char* filename);
+
+mrb_value
+mrbrs_session_load(mrb_state* mrb, mrbc_context* cxt, const char* s, size_t len);
+
+struct mrb_parser_state*
+mrbrs_session_parse(mrb_state* mrb, mrbc_context* session, const char* s, size_t len);
+
+mrb_value
+mrbrs_bool_value(bool value);
+
+mrb_value
+mrbrs_nil_value(void);
+
+mrb_value
+mrbrs_fixnum_value(mrb_int value);
+
+mrb_value
+mrbrs_symbol_value(mrb_sym sym);
+
+void
+mrbrs_fixnum_limits(mrb_int* out_min, mrb_int* out_max);
+
+bool
+mrbrs_fixnum_get(mrb_value value, mrb_int* out);
+
+bool
+mrbrs_float_get(mrb_value value, mrb_float* out);
+
+bool
+mrbrs_symbol_get(mrb_value value, mrb_sym* out);
+
+bool
+mrbrs_nil_p(mrb_value value);
+
+void*
+mrbrs_ptr(mrb_value value);
+
+mrb_value
+mrbrs_float_value(mrb_state* mrb, mrb_float value);
+
+const char*
+mrbrs_str_ptr(mrb_value str, size_t* out_len);
+
 mrb_value
 mrbrs_str_new(mrb_state* mrb, const char* p, size_t len);
 
 mrb_value
 mrbrs_str_new_static(mrb_state* mrb, const char* p, size_t len);
 
+mrb_value
+mrbrs_obj_as_string(mrb_state* mrb, mrb_value obj);
+
+mrb_value
+mrbrs_str_buf_new(mrb_state* mrb, size_t capa);
+
+void
+mrbrs_str_cat(mrb_state* mrb, mrb_value str, const char* p, size_t len);
+
+void
+mrbrs_str_append(mrb_state* mrb, mrb_value str, mrb_value other);
+
+void
+mrbrs_str_resize(mrb_state* mrb, mrb_value str, mrb_int len);
+
+mrb_value
+mrbrs_str_substr(mrb_state* mrb, mrb_value str, mrb_int beg, mrb_int len);
+
+mrb_value
+mrbrs_str_dup(mrb_state* mrb, mrb_value str);
+
+void
+mrbrs_obj_freeze(mrb_state* mrb, mrb_value obj);
+
+bool
+mrbrs_frozen_p(mrb_value obj);
+
 mrb_value
 mrbrs_intern(mrb_state* mrb, const char* p, size_t len);
 
@@ -63,5 +171,80 @@ mrbrs_hash_new(mrb_state* mrb);
 void
 mrbrs_hash_set(mrb_state* mrb, mrb_value hash, mrb_value key, mrb_value value);
 
+mrb_value
+mrbrs_hash_entries(mrb_state* mrb, mrb_value hash);
+
+mrb_value
+mrbrs_ary_new(mrb_state* mrb);
+
+void
+mrbrs_ary_push(mrb_state* mrb, mrb_value ary, mrb_value value);
+
+const mrb_value*
+mrbrs_ary_ptr(mrb_value ary, size_t* out_len);
+
+void
+mrbrs_define_global_const(mrb_state* mrb, const char* name, mrb_value value);
+
+mrb_value
+mrbrs_gv_get(mrb_state* mrb, const char* name);
+
+void
+mrbrs_gv_set(mrb_state* mrb, const char* name, mrb_value value);
+
+struct RObject*
+mrbrs_exc_new(mrb_state* mrb, struct RClass* klass, const char* message, size_t len);
+
+const char*
+mrbrs_caller_location(mrb_state* mrb, int32_t* out_line);
+
 bool
 mrbrs_equal(mrb_state* mrb, mrb_value a, mrb_value b);
+
+mrb_value
+mrbrs_funcall(mrb_state* mrb, mrb_value self, mrb_sym mid, mrb_int argc, const mrb_value* argv);
+
+bool
+mrbrs_respond_to(mrb_state* mrb, mrb_value obj, mrb_sym mid);
+
+struct RClass*
+mrbrs_singleton_class(mrb_state* mrb, mrb_value obj);
+
+mrb_value
+mrbrs_class_path(mrb_state* mrb, struct RClass* klass);
+
+struct RClass*
+mrbrs_path2class(mrb_state* mrb, const char* path, size_t len);
+
+mrb_value
+mrbrs_obj_alloc(mrb_state* mrb, struct RClass* klass);
+
+mrb_value
+mrbrs_obj_ivars(mrb_state* mrb, mrb_value obj);
+
+void
+mrbrs_iv_set(mrb_state* mrb, mrb_value obj, mrb_value name, mrb_value value);
+
+void
+mrbrs_obj_set_class(mrb_state* mrb, mrb_value obj, struct RClass* klass);
+
+void
+mrbrs_undef_method(mrb_state* mrb, struct RClass* klass, const char* name);
+
+void
+mrbrs_undef_class_method(mrb_state* mrb, struct RClass* klass, const char* name);
+
+void
+mrbrs_remove_const(mrb_state* mrb, struct RClass* klass, const char* name);
+
+void
+mrbrs_sandbox_block_only(mrb_state* mrb, struct RClass* klass, const char* name);
+
+void
+mrbrs_sandbox_guard_raise(mrb_state* mrb);
+
+mrb_value
+mrbrs_sandbox_core_classes(mrb_state* mrb);
+
+void
+mrbrs_sandbox_protect_classes(mrb_state* mrb, mrb_value classes);
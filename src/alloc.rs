@@ -0,0 +1,182 @@
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::os::raw::c_void;
+use std::ptr;
+
+use mrb_sys as sys;
+
+// mruby doesn't tell the allocator how large a block was when reallocating or
+// freeing it, so we prefix every allocation with a header recording its size.
+// the header is padded out to the alignment we hand out to mruby
+const ALIGN: usize = 16;
+const HEADER: usize = ALIGN;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes currently allocated by the interpreter
+    pub current: usize,
+    /// Highest value `current` has reached
+    pub peak: usize,
+    /// Allocation cap set with `MrbBuilder::memory_limit`, if any
+    pub limit: Option<usize>,
+}
+
+pub(crate) struct Allocator {
+    limit: Option<usize>,
+    current: Cell<usize>,
+    peak: Cell<usize>,
+
+    // mruby aborts if an allocation fails while it is booting, as there's
+    // nowhere to raise NoMemoryError yet. so the limit only takes effect once
+    // the state is open
+    enforcing: Cell<bool>,
+}
+
+impl Allocator {
+    pub fn new(limit: Option<usize>) -> Self {
+        Allocator {
+            limit,
+            current: Cell::new(0),
+            peak: Cell::new(0),
+            enforcing: Cell::new(false),
+        }
+    }
+
+    /// Starts enforcing the limit. Returns false if booting already took
+    /// more memory than the limit allows.
+    pub fn enforce_limit(&self) -> bool {
+        self.enforcing.set(true);
+        self.limit.map(|limit| self.current.get() <= limit).unwrap_or(true)
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            current: self.current.get(),
+            peak: self.peak.get(),
+            limit: self.limit,
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut c_void, size: usize) -> *mut c_void {
+        let (base, old_size) = if ptr.is_null() {
+            (ptr::null_mut(), 0)
+        } else {
+            let base = (ptr as *mut u8).sub(HEADER);
+            (base, *(base as *const usize))
+        };
+
+        if size == 0 {
+            if !base.is_null() {
+                alloc::dealloc(base, layout(old_size));
+                self.current.set(self.current.get() - old_size);
+            }
+            return ptr::null_mut();
+        }
+
+        let current = self.current.get() - old_size + size;
+
+        if let Some(limit) = self.limit.filter(|_| self.enforcing.get()) {
+            // returning NULL makes mruby run a full GC and retry, then raise
+            // NoMemoryError if we're still over the limit
+            if size > old_size && current > limit {
+                return ptr::null_mut();
+            }
+        }
+
+        let new_layout = match size.checked_add(HEADER).map(|total| Layout::from_size_align(total, ALIGN)) {
+            Some(Ok(layout)) => layout,
+            _ => return ptr::null_mut(),
+        };
+
+        let new_base = if base.is_null() {
+            alloc::alloc(new_layout)
+        } else {
+            alloc::realloc(base, layout(old_size), new_layout.size())
+        };
+
+        if new_base.is_null() {
+            return ptr::null_mut();
+        }
+
+        *(new_base as *mut usize) = size;
+
+        self.current.set(current);
+        if current > self.peak.get() {
+            self.peak.set(current);
+        }
+
+        new_base.add(HEADER) as *mut c_void
+    }
+}
+
+fn layout(size: usize) -> Layout {
+    // we already validated this layout when the block was allocated
+    unsafe { Layout::from_size_align_unchecked(size + HEADER, ALIGN) }
+}
+
+/// Safety: `ud` must point to the `Allocator` the state was opened with.
+/// This is called from C, so it must never panic.
+pub(crate) unsafe extern "C" fn allocf(
+    _mrb: *mut sys::mrb_state,
+    ptr: *mut c_void,
+    size: sys::size_t,
+    ud: *mut c_void,
+) -> *mut c_void {
+    let allocator = &*(ud as *const Allocator);
+
+    match size.try_into() {
+        Ok(size) => allocator.realloc(ptr, size),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Error};
+
+    #[test]
+    fn test_memory_stats() {
+        let mut mrb = Mrb::open();
+
+        let stats = mrb.memory_stats();
+        assert!(stats.current > 0);
+        assert!(stats.peak >= stats.current);
+        assert_eq!(None, stats.limit);
+
+        mrb.try_context(|mrb| {
+            mrb.load_string("'a' * 1_000_000")?;
+            Ok(())
+        }).expect("try_context");
+
+        assert!(mrb.memory_stats().peak >= 1_000_000);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut mrb = Mrb::builder()
+            .memory_limit(8 * 1024 * 1024)
+            .open()
+            .unwrap();
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string("'a' * (64 * 1024 * 1024)").unwrap_err();
+            assert_eq!("Out of memory (NoMemoryError)", format!("{:?}", err));
+
+            // the interpreter is still usable after hitting the limit
+            let val = mrb.load_string("1 + 2").unwrap();
+            assert_eq!("3", mrb.inspect(val));
+        });
+
+        let stats = mrb.memory_stats();
+        assert_eq!(Some(8 * 1024 * 1024), stats.limit);
+        assert!(stats.peak <= 8 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_memory_limit_too_small() {
+        // too small to even boot the core library
+        let err = Mrb::builder().memory_limit(1024).open().err().unwrap();
+        assert_eq!(Error::Open, err);
+    }
+}
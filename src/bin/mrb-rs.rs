@@ -0,0 +1,17 @@
+//! A plain interactive shell with nothing but the core library loaded.
+//! Applications that want their own classes available should build their
+//! own binary around `Repl`, registering them with `Repl::init`.
+
+use std::process;
+
+use mrb::Mrb;
+use mrb::repl::Repl;
+
+fn main() {
+    let mut mrb = Mrb::open();
+
+    if let Err(err) = Repl::new().run(&mut mrb) {
+        eprintln!("mrb-rs: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,166 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+use mrb::{Mrb, Context, MrbException, Error, Module};
+
+const USAGE: &str = "usage: mrb-run [--memory-limit BYTES] [--timeout SECONDS] FILE [ARGS...]";
+
+struct Options {
+    memory_limit: Option<usize>,
+    timeout: Option<Duration>,
+    path: String,
+    args: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut memory_limit = None;
+    let mut timeout = None;
+
+    let path = loop {
+        let arg = args.next().ok_or("no file given")?;
+
+        match arg.as_str() {
+            "--memory-limit" => {
+                let bytes = args.next().ok_or("--memory-limit needs a value")?;
+                memory_limit = Some(bytes.parse().map_err(|_| format!("invalid memory limit: {}", bytes))?);
+            }
+            "--timeout" => {
+                let secs = args.next().ok_or("--timeout needs a value")?;
+                let duration = secs.parse().ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| format!("invalid timeout: {}", secs))?;
+                timeout = Some(duration);
+            }
+            "--" => break args.next().ok_or("no file given")?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => break arg,
+        }
+    };
+
+    Ok(Options { memory_limit, timeout, path, args: args.collect() })
+}
+
+fn run<'mrb>(mrb: &Context<'mrb>, path: &str, source: Vec<u8>, args: &[String]) -> Result<(), MrbException<'mrb>> {
+    let argv = mrb.new_array()?;
+
+    for arg in args {
+        mrb.array_push(argv, mrb.new_string(arg)?)?;
+    }
+
+    mrb.define_global_const("ARGV", argv)?;
+
+    // source is passed through as is, scripts needn't be UTF-8
+    mrb.load_module(path, &Module::from_bytes(source))?;
+
+    Ok(())
+}
+
+fn report(exc: MrbException) {
+    if exc.abort().is_some() {
+        eprintln!("mrb-run: {}", Error::from(exc));
+        return;
+    }
+
+    let backtrace = exc.backtrace();
+
+    match backtrace.first() {
+        Some(line) => eprintln!("{}: {:?}", line, exc),
+        None => eprintln!("{:?}", exc),
+    }
+
+    for line in backtrace.iter().skip(1) {
+        eprintln!("\tfrom {}", line);
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("mrb-run: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let source = match fs::read(&options.path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("mrb-run: {}: {}", options.path, err);
+            process::exit(2);
+        }
+    };
+
+    let mut builder = Mrb::builder();
+
+    if let Some(bytes) = options.memory_limit {
+        builder = builder.memory_limit(bytes);
+    }
+
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    let mut mrb = match builder.open() {
+        Ok(mrb) => mrb,
+        Err(err) => {
+            eprintln!("mrb-run: {}", err);
+            process::exit(2);
+        }
+    };
+
+    let ok = mrb.context(|mrb| {
+        run(mrb, &options.path, source, &options.args)
+            .map_err(report)
+            .is_ok()
+    });
+
+    if !ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_args, Options};
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse(&["script.rb", "--timeout", "x"]).unwrap();
+        assert_eq!(None, options.memory_limit);
+        assert_eq!(None, options.timeout);
+        assert_eq!("script.rb", options.path);
+        assert_eq!(vec!["--timeout", "x"], options.args);
+
+        let options = parse(&["--memory-limit", "1024", "--timeout", "1.5", "--", "--script.rb"]).unwrap();
+        assert_eq!(Some(1024), options.memory_limit);
+        assert_eq!(Some(Duration::from_millis(1500)), options.timeout);
+        assert_eq!("--script.rb", options.path);
+        assert!(options.args.is_empty());
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert_eq!("no file given", parse(&[]).err().unwrap());
+        assert_eq!("no file given", parse(&["--"]).err().unwrap());
+        assert_eq!("unknown option: --verbose", parse(&["--verbose", "script.rb"]).err().unwrap());
+
+        assert_eq!("--memory-limit needs a value", parse(&["--memory-limit"]).err().unwrap());
+        assert_eq!("invalid memory limit: 1k", parse(&["--memory-limit", "1k", "script.rb"]).err().unwrap());
+        assert_eq!("invalid memory limit: -1", parse(&["--memory-limit", "-1", "script.rb"]).err().unwrap());
+
+        assert_eq!("--timeout needs a value", parse(&["--timeout"]).err().unwrap());
+        assert_eq!("invalid timeout: soon", parse(&["--timeout", "soon", "script.rb"]).err().unwrap());
+        assert_eq!("invalid timeout: -1", parse(&["--timeout", "-1", "script.rb"]).err().unwrap());
+        assert_eq!("invalid timeout: inf", parse(&["--timeout", "inf", "script.rb"]).err().unwrap());
+        assert_eq!("invalid timeout: NaN", parse(&["--timeout", "NaN", "script.rb"]).err().unwrap());
+        assert_eq!("invalid timeout: 1e20", parse(&["--timeout", "1e20", "script.rb"]).err().unwrap());
+    }
+}
@@ -17,7 +17,7 @@ pub unsafe fn into_mruby<'mrb, R>(mrb: *mut mrb_state, f: impl FnOnce() -> R) ->
     let mut panic_info: PanicSlot = None;
 
     // install pointer to panic_info in mrb_state's user data field
-    let ud = (*mrb).ud as *mut mrb_sys::mrbrs_ud;
+    let ud = mrb_sys::mrbrs_get_ud(mrb);
     let mut prev_panic_info = &mut panic_info as *mut PanicSlot as *mut c_void;
     mem::swap(&mut prev_panic_info, &mut (*ud).panic_info);
 
@@ -45,7 +45,7 @@ pub unsafe fn into_mruby<'mrb, R>(mrb: *mut mrb_state, f: impl FnOnce() -> R) ->
 
 /// Safety takes raw pointer
 pub unsafe fn into_rust<R>(mrb: *mut mrb_state, f: impl FnOnce() -> R + UnwindSafe) -> Result<R, ()> {
-    let ud = &mut *((*mrb).ud as *mut mrb_sys::mrbrs_ud);
+    let ud = &mut *mrb_sys::mrbrs_get_ud(mrb);
 
     let mut panic_slot = match NonNull::new((*ud).panic_info as *mut PanicSlot) {
         Some(slot) => slot,
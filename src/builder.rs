@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use crate::{Mrb, Error};
+use crate::alloc::Allocator;
+use crate::limits::{self, Limits};
+use crate::sandbox::SandboxPolicy;
+use crate::state::MrbState;
+
+#[derive(Default)]
+pub struct MrbBuilder {
+    memory_limit: Option<usize>,
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+    sandbox: Option<SandboxPolicy>,
+}
+
+impl MrbBuilder {
+    pub fn new() -> Self {
+        MrbBuilder::default()
+    }
+
+    /// Caps the number of bytes the interpreter may have allocated at once.
+    /// Allocations beyond the cap raise `NoMemoryError` in Ruby.
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Aborts a script after it has executed this many VM instructions in
+    /// a single call to `Mrb::context`. Requires mruby to be built with
+    /// `MRB_ENABLE_DEBUG_HOOK` and this crate with the `debug-hook` feature,
+    /// otherwise `open` fails.
+    pub fn instruction_limit(mut self, instructions: u64) -> Self {
+        self.instruction_limit = Some(instructions);
+        self
+    }
+
+    /// Aborts a script once a single call to `Mrb::context` has been running
+    /// for longer than `timeout`. Requires mruby to be built with
+    /// `MRB_ENABLE_DEBUG_HOOK` and this crate with the `debug-hook` feature,
+    /// otherwise `open` fails.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Strips the methods and constants denied by `policy` from the core
+    /// library before any scripts are run.
+    pub fn sandbox(mut self, policy: SandboxPolicy) -> Self {
+        self.sandbox = Some(policy);
+        self
+    }
+
+    /// Opens the interpreter. Fails with `Error::Unsupported` when execution
+    /// limits were asked for but mruby has no code fetch hook to enforce
+    /// them with, as a loop in pure Ruby could then never be stopped.
+    pub fn open(self) -> Result<Mrb, Error> {
+        if (self.instruction_limit.is_some() || self.timeout.is_some()) && !limits::supported() {
+            return Err(Error::Unsupported("execution limits require mruby built with MRB_ENABLE_DEBUG_HOOK"));
+        }
+
+        let allocator = Allocator::new(self.memory_limit);
+        let limits = Limits::new(self.instruction_limit, self.timeout);
+        let state = MrbState::open(allocator, limits).map_err(|()| Error::Open)?;
+        let mut mrb = Mrb { state, output: None, sandbox: None };
+
+        if let Some(policy) = self.sandbox {
+            mrb.try_context(|ctx| ctx.apply_sandbox(&policy))?;
+            mrb.sandbox = Some(policy);
+        }
+
+        Ok(mrb)
+    }
+}
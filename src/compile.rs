@@ -0,0 +1,268 @@
+use std::cmp;
+use std::convert::TryInto;
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+use std::slice;
+
+use mrb_sys as sys;
+
+use crate::{Context, Error, MrbResult};
+use crate::marker::Invariant;
+use crate::object::MrbValue;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or warning reported by the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "{}:{}: {}", self.line, self.column, self.message),
+            Severity::Warning => write!(f, "{}:{}: warning: {}", self.line, self.column, self.message),
+        }
+    }
+}
+
+pub(crate) struct Parser<'mrb> {
+    ptr: *mut sys::mrb_parser_state,
+    _inv: Invariant<'mrb>,
+}
+
+impl<'mrb> Parser<'mrb> {
+    /// Safety: takes ownership of a parser state returned by mruby.
+    pub unsafe fn new(ptr: *mut sys::mrb_parser_state) -> Self {
+        Parser {
+            ptr,
+            _inv: Invariant::phantom(),
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut sys::mrb_parser_state {
+        self.ptr
+    }
+
+    pub fn has_errors(&self) -> bool {
+        unsafe { (*self.ptr).nerr > 0 }
+    }
+
+    // mirrors mirb's check for input which could still be completed by
+    // reading more lines
+    pub fn is_incomplete(&self) -> bool {
+        unsafe {
+            let parser = &*self.ptr;
+
+            if !parser.parsing_heredoc.is_null() || !parser.lex_strterm.is_null() {
+                return true;
+            }
+
+            if parser.nerr == 0 || parser.error_buffer[0].message.is_null() {
+                return false;
+            }
+
+            let message = CStr::from_ptr(parser.error_buffer[0].message).to_string_lossy();
+            message.starts_with("syntax error, unexpected $end")
+                || message.starts_with("syntax error, unexpected end of file")
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        unsafe {
+            let parser = &*self.ptr;
+
+            // the parser only keeps the first few messages of each kind, even
+            // though it counts them all
+            let nerr = cmp::min(parser.nerr.try_into().unwrap_or(usize::MAX), parser.error_buffer.len());
+            let nwarn = cmp::min(parser.nwarn.try_into().unwrap_or(usize::MAX), parser.warn_buffer.len());
+
+            let errors = parser.error_buffer[..nerr].iter()
+                .map(|message| diagnostic(Severity::Error, message));
+
+            let warnings = parser.warn_buffer[..nwarn].iter()
+                .map(|message| diagnostic(Severity::Warning, message));
+
+            let mut diagnostics = errors.chain(warnings).collect::<Vec<_>>();
+            diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+            diagnostics
+        }
+    }
+}
+
+impl<'mrb> Drop for Parser<'mrb> {
+    fn drop(&mut self) {
+        unsafe { sys::mrb_parser_free(self.ptr) };
+    }
+}
+
+unsafe fn diagnostic(severity: Severity, message: &sys::mrb_parser_message) -> Diagnostic {
+    let text = if message.message.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(message.message).to_string_lossy().into_owned()
+    };
+
+    Diagnostic {
+        severity,
+        line: message.lineno.into(),
+        column: message.column.try_into().unwrap_or(0),
+        message: text,
+    }
+}
+
+impl<'mrb> Context<'mrb> {
+    pub(crate) fn parser(&self, code: &str, filename: &str) -> MrbResult<'mrb, Parser<'mrb>> {
+        let filename = CString::new(filename).expect("CString::from");
+
+        let ptr = self.boundary(|| unsafe {
+            sys::mrbrs_parse(
+                self.mrb,
+                code.as_ptr() as *const i8,
+                code.len().try_into().unwrap(),
+                filename.as_ptr(),
+            )
+        })?;
+
+        Ok(unsafe { Parser::new(ptr) })
+    }
+
+    /// Parses `code` without running it, returning every error and warning
+    /// reported by the parser. mruby keeps at most ten of each.
+    pub fn parse(&self, code: &str, filename: &str) -> MrbResult<'mrb, Vec<Diagnostic>> {
+        Ok(self.parser(code, filename)?.diagnostics())
+    }
+
+    /// Compiles `code` to mruby bytecode in the RITE binary format, as
+    /// produced by `mrbc`.
+    pub fn compile(&self, code: &str, filename: &str) -> Result<Vec<u8>, Error> {
+        let parser = self.parser(code, filename)?;
+
+        if parser.has_errors() {
+            return Err(Error::Syntax(parser.diagnostics()));
+        }
+
+        let mut len: sys::size_t = 0;
+
+        let bin = self.boundary(|| unsafe {
+            sys::mrbrs_compile(self.mrb, parser.as_ptr(), &mut len as *mut _)
+        })?;
+
+        unsafe {
+            let bytes = slice::from_raw_parts(bin, len.try_into().unwrap()).to_vec();
+            sys::mrb_free(self.mrb, bin as *mut c_void);
+            Ok(bytes)
+        }
+    }
+
+    /// Runs bytecode produced by `compile` or `mrbc`. Images that are
+    /// truncated, corrupt or from an incompatible mruby raise `ScriptError`.
+    pub fn load_bytecode(&self, bytecode: &[u8]) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_load_irep_buf(
+                self.mrb,
+                bytecode.as_ptr(),
+                bytecode.len().try_into().unwrap(),
+            )
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Context, Error, Diagnostic, Severity};
+
+    fn load(mrb: &Context, bytecode: &[u8]) -> Result<String, String> {
+        mrb.load_bytecode(bytecode)
+            .map(|val| mrb.inspect(val).to_string())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_compile() {
+        let mut mrb = Mrb::open();
+
+        let bytecode = mrb.compile("1 + 2", "test.rb").unwrap();
+        assert_eq!(b"RITE", &bytecode[..4]);
+    }
+
+    #[test]
+    fn test_compile_syntax_error() {
+        let mut mrb = Mrb::open();
+
+        let err = mrb.compile("x = 1\ny = (\n", "test.rb").unwrap_err();
+
+        match err {
+            Error::Syntax(diagnostics) => {
+                let Diagnostic { severity, line, .. } = diagnostics[0].clone();
+                assert_eq!(Severity::Error, severity);
+                assert!(line >= 2);
+                assert!(diagnostics[0].message.contains("syntax error"));
+            }
+            err => panic!("expected syntax error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            assert_eq!(Vec::<Diagnostic>::new(), mrb.parse("1 + 2", "test.rb").unwrap());
+
+            let diagnostics = mrb.parse("def foo\n  1 +\nend\n", "test.rb").unwrap();
+            assert_eq!(1, diagnostics.len());
+            assert_eq!(Severity::Error, diagnostics[0].severity);
+            assert_eq!(3, diagnostics[0].line);
+            assert!(diagnostics[0].to_string().starts_with("3:"));
+
+            // parsing doesn't run anything
+            mrb.parse("raise 'hello'", "test.rb").unwrap();
+        });
+    }
+
+    #[test]
+    fn test_load_bytecode() {
+        let mut mrb = Mrb::open();
+
+        let bytecode = mrb.compile("def add(a, b); a + b; end; add(1, 2)", "test.rb").unwrap();
+
+        mrb.context(|mrb| {
+            assert_eq!("3", load(mrb, &bytecode).unwrap());
+            assert_eq!("7", mrb.inspect(mrb.load_string("add(3, 4)").unwrap()));
+        });
+    }
+
+    #[test]
+    fn test_load_bad_bytecode() {
+        let mut mrb = Mrb::open();
+
+        let bytecode = mrb.compile("1 + 2", "test.rb").unwrap();
+
+        let mut corrupt = bytecode.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+
+        let mut version = bytecode.clone();
+        version[4..8].copy_from_slice(b"9999");
+
+        mrb.context(|mrb| {
+            assert_eq!("bytecode is truncated (ScriptError)", load(mrb, &bytecode[..10]).unwrap_err());
+            assert_eq!("bytecode is truncated (ScriptError)", load(mrb, &bytecode[..bytecode.len() - 1]).unwrap_err());
+            assert_eq!("not mruby bytecode (ScriptError)", load(mrb, b"puts 'this is not bytecode'").unwrap_err());
+            assert_eq!("bytecode checksum mismatch (ScriptError)", load(mrb, &corrupt).unwrap_err());
+            assert!(load(mrb, &version).unwrap_err().starts_with("incompatible bytecode version"));
+        });
+    }
+}
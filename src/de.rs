@@ -0,0 +1,472 @@
+use std::cell::RefCell;
+use std::error;
+use std::fmt::{self, Display};
+use std::os::raw::c_void;
+use std::vec;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Unexpected, Visitor};
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult, MAX_DEPTH};
+use crate::object::{MrbValue, MrbException};
+
+/// Converts a Ruby value to a Rust value. Hashes may be keyed by symbols or
+/// strings, and enum variants are read from a variant name or a Hash with a
+/// single key, as produced by `to_value`. Values which don't fit raise
+/// `TypeError`, with a message naming where in `value` the problem was,
+/// such as `config.servers[2].port`. Values which contain themselves or
+/// are nested too deeply raise `ArgumentError`.
+pub fn from_value<'mrb, T: DeserializeOwned>(ctx: &Context<'mrb>, value: MrbValue<'mrb>) -> MrbResult<'mrb, T> {
+    let parents = RefCell::new(Vec::new());
+
+    T::deserialize(Deserializer { ctx, value, parents: &parents }).map_err(|err| match err {
+        Error::Exception(exc) => exc,
+        Error::Custom { message, path } => {
+            let path = path.iter().rev().map(String::as_str).collect::<String>();
+
+            match path.trim_start_matches('.') {
+                "" => ctx.core_exception("TypeError", &message),
+                path => ctx.core_exception("TypeError", &format!("{} at {}", message, path)),
+            }
+        }
+    })
+}
+
+#[derive(Debug)]
+pub(crate) enum Error<'mrb> {
+    Exception(MrbException<'mrb>),
+    Custom {
+        message: String,
+        // segments such as `.port` or `[2]`, innermost first
+        path: Vec<String>,
+    },
+}
+
+impl<'mrb> Error<'mrb> {
+    fn at(self, segment: impl FnOnce() -> String) -> Self {
+        match self {
+            Error::Custom { message, mut path } => {
+                path.push(segment());
+                Error::Custom { message, path }
+            }
+            err => err,
+        }
+    }
+}
+
+impl<'mrb> From<MrbException<'mrb>> for Error<'mrb> {
+    fn from(exc: MrbException<'mrb>) -> Self {
+        Error::Exception(exc)
+    }
+}
+
+impl<'mrb> Display for Error<'mrb> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Exception(exc) => write!(f, "{:?}", exc),
+            Error::Custom { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl<'mrb> error::Error for Error<'mrb> {}
+
+impl<'mrb> de::Error for Error<'mrb> {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Custom { message: msg.to_string(), path: Vec::new() }
+    }
+}
+
+type Result<'mrb, T> = std::result::Result<T, Error<'mrb>>;
+
+// the Arrays and Hashes enclosing the value being deserialized
+type Parents = RefCell<Vec<*mut c_void>>;
+
+struct Deserializer<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    value: MrbValue<'mrb>,
+    parents: &'a Parents,
+}
+
+impl<'a, 'mrb> Deserializer<'a, 'mrb> {
+    fn invalid_type(&self, expected: &dyn de::Expected) -> Error<'mrb> {
+        de::Error::invalid_type(Unexpected::Other(&self.ctx.class_name(self.value)), expected)
+    }
+
+    // runs `f` with this Array or Hash recorded as a parent, so that cyclic
+    // or overly deep values fail rather than overflow the stack
+    fn nested<T>(&self, f: impl FnOnce() -> Result<'mrb, T>) -> Result<'mrb, T> {
+        let ptr = self.value.as_ptr();
+
+        if self.parents.borrow().contains(&ptr) {
+            return Err(self.ctx.core_exception("ArgumentError", "circular reference detected in deserialization").into());
+        }
+
+        if self.parents.borrow().len() == MAX_DEPTH {
+            return Err(self.ctx.core_exception("ArgumentError", &format!("nesting of {} is too deep to deserialize", MAX_DEPTH + 1)).into());
+        }
+
+        self.parents.borrow_mut().push(ptr);
+        let result = f();
+        self.parents.borrow_mut().pop();
+        result
+    }
+}
+
+// the path segment for a value stored under `key` in a Hash
+fn key_segment<'mrb>(ctx: &Context<'mrb>, key: MrbValue<'mrb>) -> String {
+    match key.as_symbol().map(|sym| ctx.symbol_name(sym)).or_else(|| ctx.as_string(key)) {
+        Some(name) => format!(".{}", name),
+        None => format!("[{}]", ctx.inspect(key)),
+    }
+}
+
+impl<'de, 'a, 'mrb> de::Deserializer<'de> for Deserializer<'a, 'mrb> {
+    type Error = Error<'mrb>;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<'mrb, V::Value> {
+        let raw = self.value.as_raw();
+
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_FALSE if self.value.is_nil() => visitor.visit_unit(),
+            sys::mrb_vtype_MRB_TT_FALSE => visitor.visit_bool(false),
+            sys::mrb_vtype_MRB_TT_TRUE => visitor.visit_bool(true),
+            sys::mrb_vtype_MRB_TT_FIXNUM => visitor.visit_i64(self.value.as_i64().expect("Fixnum")),
+            sys::mrb_vtype_MRB_TT_FLOAT => visitor.visit_f64(self.value.as_f64().expect("Float")),
+            sys::mrb_vtype_MRB_TT_SYMBOL => visitor.visit_string(self.ctx.symbol_name(self.value.as_symbol().expect("Symbol"))),
+            sys::mrb_vtype_MRB_TT_STRING => {
+                match String::from_utf8(self.ctx.string_bytes(self.value).expect("String")) {
+                    Ok(string) => visitor.visit_string(string),
+                    Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+                }
+            }
+            sys::mrb_vtype_MRB_TT_ARRAY => self.nested(|| {
+                let entries = self.ctx.array_entries(self.value).expect("Array");
+                let len = entries.len();
+
+                let mut seq = SeqAccess { ctx: self.ctx, entries: entries.into_iter(), index: 0, parents: self.parents };
+                let value = visitor.visit_seq(&mut seq)?;
+
+                match seq.entries.len() {
+                    0 => Ok(value),
+                    _ => Err(de::Error::invalid_length(len, &"fewer elements in Array")),
+                }
+            }),
+            sys::mrb_vtype_MRB_TT_HASH => self.nested(|| {
+                let entries = self.ctx.hash_entries(self.value)?;
+                let len = entries.len();
+
+                let mut map = MapAccess { ctx: self.ctx, entries: entries.into_iter(), value: None, parents: self.parents };
+                let value = visitor.visit_map(&mut map)?;
+
+                match map.entries.len() {
+                    0 => Ok(value),
+                    _ => Err(de::Error::invalid_length(len, &"fewer entries in Hash")),
+                }
+            }),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<'mrb, V::Value> {
+        if self.value.is_nil() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<'mrb, V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<'mrb, V::Value> {
+        match self.value.as_raw().tt {
+            sys::mrb_vtype_MRB_TT_SYMBOL | sys::mrb_vtype_MRB_TT_STRING => {
+                visitor.visit_enum(EnumAccess { ctx: self.ctx, variant: self.value, value: None, parents: self.parents })
+            }
+            sys::mrb_vtype_MRB_TT_HASH => self.nested(|| match self.ctx.hash_entries(self.value)?.as_slice() {
+                [(variant, value)] => visitor.visit_enum(EnumAccess { ctx: self.ctx, variant: *variant, value: Some(*value), parents: self.parents }),
+                _ => Err(de::Error::invalid_value(Unexpected::Map, &"a Hash with a single key")),
+            }),
+            _ => Err(self.invalid_type(&"a Symbol, String or Hash")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct SeqAccess<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    entries: vec::IntoIter<MrbValue<'mrb>>,
+    index: usize,
+    parents: &'a Parents,
+}
+
+impl<'de, 'a, 'mrb> de::SeqAccess<'de> for SeqAccess<'a, 'mrb> {
+    type Error = Error<'mrb>;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<'mrb, Option<T::Value>> {
+        let value = match self.entries.next() {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let index = self.index;
+        self.index += 1;
+
+        seed.deserialize(Deserializer { ctx: self.ctx, value, parents: self.parents })
+            .map(Some)
+            .map_err(|err| err.at(|| format!("[{}]", index)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct MapAccess<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    entries: vec::IntoIter<(MrbValue<'mrb>, MrbValue<'mrb>)>,
+    value: Option<(MrbValue<'mrb>, MrbValue<'mrb>)>,
+    parents: &'a Parents,
+}
+
+impl<'de, 'a, 'mrb> de::MapAccess<'de> for MapAccess<'a, 'mrb> {
+    type Error = Error<'mrb>;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<'mrb, Option<K::Value>> {
+        let (key, value) = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        self.value = Some((key, value));
+
+        seed.deserialize(Deserializer { ctx: self.ctx, value: key, parents: self.parents })
+            .map(Some)
+            .map_err(|err| err.at(|| key_segment(self.ctx, key)))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<'mrb, V::Value> {
+        let (key, value) = self.value.take().expect("next_value_seed called before next_key_seed");
+
+        seed.deserialize(Deserializer { ctx: self.ctx, value, parents: self.parents })
+            .map_err(|err| err.at(|| key_segment(self.ctx, key)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    variant: MrbValue<'mrb>,
+    value: Option<MrbValue<'mrb>>,
+    parents: &'a Parents,
+}
+
+impl<'de, 'a, 'mrb> de::EnumAccess<'de> for EnumAccess<'a, 'mrb> {
+    type Error = Error<'mrb>;
+    type Variant = VariantAccess<'a, 'mrb>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<'mrb, (V::Value, VariantAccess<'a, 'mrb>)> {
+        let variant = seed.deserialize(Deserializer { ctx: self.ctx, value: self.variant, parents: self.parents })?;
+        Ok((variant, VariantAccess { ctx: self.ctx, variant: self.variant, value: self.value, parents: self.parents }))
+    }
+}
+
+struct VariantAccess<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    variant: MrbValue<'mrb>,
+    value: Option<MrbValue<'mrb>>,
+    parents: &'a Parents,
+}
+
+impl<'a, 'mrb> VariantAccess<'a, 'mrb> {
+    fn value(&self, expected: &dyn de::Expected) -> Result<'mrb, Deserializer<'a, 'mrb>> {
+        match self.value {
+            Some(value) => Ok(Deserializer { ctx: self.ctx, value, parents: self.parents }),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, expected)),
+        }
+    }
+
+    fn in_variant<T>(&self, result: Result<'mrb, T>) -> Result<'mrb, T> {
+        result.map_err(|err| err.at(|| key_segment(self.ctx, self.variant)))
+    }
+}
+
+impl<'de, 'a, 'mrb> de::VariantAccess<'de> for VariantAccess<'a, 'mrb> {
+    type Error = Error<'mrb>;
+
+    fn unit_variant(self) -> Result<'mrb, ()> {
+        match self.value {
+            Some(value) => {
+                let result = de::Deserialize::deserialize(Deserializer { ctx: self.ctx, value, parents: self.parents });
+                self.in_variant(result)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<'mrb, T::Value> {
+        let result = seed.deserialize(self.value(&"newtype variant")?);
+        self.in_variant(result)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<'mrb, V::Value> {
+        let result = de::Deserializer::deserialize_seq(self.value(&"tuple variant")?, visitor);
+        self.in_variant(result)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<'mrb, V::Value> {
+        let result = de::Deserializer::deserialize_map(self.value(&"struct variant")?, visitor);
+        self.in_variant(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+    use serde::de::IgnoredAny;
+
+    use crate::{Mrb, Context, MAX_DEPTH, from_value};
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Config {
+        name: String,
+        servers: Vec<Server>,
+        tags: HashMap<String, f64>,
+        mode: Option<Mode>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Server {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        tls: bool,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Mode {
+        Fast,
+        Limited(u32),
+        Custom { level: i8 },
+    }
+
+    fn from_ruby<T: serde::de::DeserializeOwned>(mrb: &Context, code: &str) -> Result<T, String> {
+        let value = mrb.load_string(code).unwrap();
+        from_value(mrb, value).map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_from_value() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let config: Config = from_ruby(mrb, r#"
+                {
+                    name: "web",
+                    servers: [{ host: "a", port: 80 }, { "host" => "b", "port" => 443, "tls" => true }],
+                    tags: { weight: 1, "ratio" => 0.5 },
+                    mode: :Fast,
+                }
+            "#).unwrap();
+
+            assert_eq!(Config {
+                name: "web".to_owned(),
+                servers: vec![
+                    Server { host: "a".to_owned(), port: 80, tls: false },
+                    Server { host: "b".to_owned(), port: 443, tls: true },
+                ],
+                tags: vec![("weight".to_owned(), 1.0), ("ratio".to_owned(), 0.5)].into_iter().collect(),
+                mode: Some(Mode::Fast),
+            }, config);
+
+            assert_eq!(Mode::Limited(3), from_ruby(mrb, "{ Limited: 3 }").unwrap());
+            assert_eq!(Mode::Custom { level: -1 }, from_ruby(mrb, "{ 'Custom' => { level: -1 } }").unwrap());
+            assert_eq!((1, None, vec![true]), from_ruby::<(u8, Option<String>, Vec<bool>)>(mrb, "[1, nil, [true]]").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_from_value_errors() {
+        #[derive(Debug, Deserialize)]
+        struct Root {
+            #[allow(dead_code)]
+            config: Config,
+        }
+
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let servers = "[{ host: 'a', port: 1 }, { host: 'b', port: 2 }, { host: 'c', port: 70000 }]";
+
+            assert_eq!(
+                "invalid value: integer `70000`, expected u16 at config.servers[2].port (TypeError)",
+                from_ruby::<Root>(mrb, &format!("{{ config: {{ name: 'x', servers: {}, tags: {{}} }} }}", servers)).unwrap_err(),
+            );
+
+            assert_eq!(
+                "missing field `port` at servers[0] (TypeError)",
+                from_ruby::<Config>(mrb, "{ name: 'x', servers: [{ host: 'a' }], tags: {} }").unwrap_err(),
+            );
+
+            assert_eq!(
+                "invalid type: string \"fast\", expected f64 at tags.speed (TypeError)",
+                from_ruby::<Config>(mrb, "{ name: 'x', servers: [], tags: { speed: 'fast' } }").unwrap_err(),
+            );
+
+            assert_eq!(
+                "invalid value: integer `300`, expected i8 at mode.Custom.level (TypeError)",
+                from_ruby::<Config>(mrb, "{ name: 'x', servers: [], tags: {}, mode: { Custom: { level: 300 } } }").unwrap_err(),
+            );
+
+            assert_eq!(
+                "invalid type: Range, expected a string (TypeError)",
+                from_ruby::<String>(mrb, "1..2").unwrap_err(),
+            );
+        });
+    }
+
+    #[test]
+    fn test_from_value_nesting() {
+        #[derive(Debug, Deserialize)]
+        struct Nested(#[allow(dead_code)] Vec<Nested>);
+
+        #[derive(Debug, Deserialize)]
+        enum Tree {
+            #[allow(dead_code)]
+            Node(Box<Tree>),
+        }
+
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let circular = "circular reference detected in deserialization (ArgumentError)";
+            assert_eq!(circular, from_ruby::<Nested>(mrb, "a = []; a << a; a").unwrap_err());
+            assert_eq!(circular, from_ruby::<IgnoredAny>(mrb, "h = {}; h[:k] = h; h").unwrap_err());
+            assert_eq!(circular, from_ruby::<Tree>(mrb, "h = {}; h[:Node] = h; h").unwrap_err());
+
+            // shared values are fine as long as they don't contain themselves
+            from_ruby::<Nested>(mrb, "a = []; [a, a]").unwrap();
+
+            let nested = |depth| format!("a = []; {}.times {{ a = [a] }}; a", depth - 1);
+            from_ruby::<Nested>(mrb, &nested(MAX_DEPTH)).unwrap();
+
+            assert_eq!(
+                "nesting of 129 is too deep to deserialize (ArgumentError)",
+                from_ruby::<IgnoredAny>(mrb, &nested(MAX_DEPTH + 1)).unwrap_err(),
+            );
+        });
+    }
+}
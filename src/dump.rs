@@ -0,0 +1,742 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::os::raw::c_void;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult, MAX_DEPTH};
+use crate::object::{MrbValue, MrbClass, MrbPtr, MrbException};
+
+const MAGIC: &[u8] = b"MRBD";
+const VERSION: u8 = 1;
+
+const NIL: u8 = b'0';
+const TRUE: u8 = b'T';
+const FALSE: u8 = b'F';
+const FIXNUM: u8 = b'i';
+const FLOAT: u8 = b'f';
+const SYMBOL: u8 = b':';
+const SYMBOL_LINK: u8 = b';';
+const STRING: u8 = b'"';
+const ARRAY: u8 = b'[';
+const HASH: u8 = b'{';
+const CLASS: u8 = b'c';
+const OBJECT: u8 = b'o';
+const USER: u8 = b'u';
+const USER_CLASS: u8 = b'C';
+const IVARS: u8 = b'I';
+const LINK: u8 = b'@';
+
+impl<'mrb> Context<'mrb> {
+    /// Encodes `value` and everything reachable from it in a compact binary
+    /// format, in the manner of Ruby's `Marshal.dump`. Objects referenced
+    /// more than once, including cyclic references, are written once and
+    /// are shared again by `load_dump`.
+    ///
+    /// Supported are nil, booleans, numbers, Strings, Symbols, Arrays,
+    /// Hashes, named classes and modules, and instances of named classes,
+    /// which are dumped with their instance variables. Instances of
+    /// subclasses of String, Array and Hash keep their class, and Hashes
+    /// their instance variables. Objects which
+    /// respond to `_dump` are instead dumped as the String it returns, and
+    /// loaded with their class's `_load`. Anything else raises `TypeError`.
+    pub fn dump(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, Vec<u8>> {
+        let mut dumper = Dumper {
+            ctx: self,
+            out: MAGIC.to_vec(),
+            objects: HashMap::new(),
+            symbols: HashMap::new(),
+            depth: 0,
+        };
+
+        dumper.out.push(VERSION);
+        dumper.write_value(value)?;
+        Ok(dumper.out)
+    }
+
+    /// Recreates a value from the output of `dump`. Classes are looked up by
+    /// name, and objects are allocated without calling `initialize`.
+    /// Malformed data raises `ArgumentError`.
+    pub fn load_dump(&self, bytes: &[u8]) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let input = match bytes.strip_prefix(MAGIC) {
+            Some([VERSION, input @ ..]) => input,
+            _ => return Err(self.core_exception("TypeError", "incompatible dump format")),
+        };
+
+        let mut loader = Loader {
+            ctx: self,
+            input,
+            objects: Vec::new(),
+            symbols: Vec::new(),
+            depth: 0,
+        };
+
+        let value = loader.read_value()?;
+
+        match loader.input {
+            [] => Ok(value),
+            _ => Err(loader.error("trailing data after dump")),
+        }
+    }
+
+    /// Makes instances of `class` dumpable, usually a class wrapping Rust
+    /// data which Ruby can't see into. `dump` encodes an instance as bytes,
+    /// and `load` is given the class and those bytes to create an instance
+    /// from. They're installed as the `_dump` and `_load` methods.
+    pub fn define_dump<D, L>(&self, class: MrbClass<'mrb>, dump: D, load: L) -> MrbResult<'mrb, ()>
+        where D: for<'sub> Fn(&Context<'sub>, MrbValue<'sub>) -> MrbResult<'sub, Vec<u8>> + 'static,
+              L: for<'sub> Fn(&Context<'sub>, MrbValue<'sub>, &[u8]) -> MrbResult<'sub, MrbValue<'sub>> + 'static,
+    {
+        let singleton = self.boundary(|| unsafe {
+            sys::mrbrs_singleton_class(self.mrb, sys::mrbrs_obj_value(class.0.as_ptr() as *mut c_void))
+        })?;
+
+        let singleton = MrbClass(unsafe { MrbPtr::new(self.mrb, singleton) });
+
+        self.define_method(class, "_dump", move |ctx, self_| {
+            let bytes = dump(ctx, self_)?;
+            ctx.new_string_bytes(&bytes)
+        })?;
+
+        self.define_method(singleton, "_load", move |ctx, class| {
+            let bytes = match ctx.arguments() {
+                [data] => ctx.string_bytes(*data),
+                _ => None,
+            };
+
+            let bytes = bytes.ok_or_else(|| ctx.core_exception("TypeError", "_load expects a String"))?;
+            load(ctx, class, &bytes)
+        })
+    }
+}
+
+struct Dumper<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    out: Vec<u8>,
+
+    // heap objects and symbol names already written, with the index later
+    // occurrences refer back to
+    objects: HashMap<*mut c_void, usize>,
+    symbols: HashMap<Vec<u8>, usize>,
+
+    depth: usize,
+}
+
+impl<'a, 'mrb> Dumper<'a, 'mrb> {
+    fn write_len(&mut self, len: usize) {
+        write_varint(&mut self.out, len as u64);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_len(bytes.len());
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn write_symbol(&mut self, name: Vec<u8>) {
+        if let Some(index) = self.symbols.get(&name) {
+            let index = *index;
+            self.out.push(SYMBOL_LINK);
+            self.write_len(index);
+        } else {
+            self.out.push(SYMBOL);
+            self.write_bytes(&name);
+            self.symbols.insert(name, self.symbols.len());
+        }
+    }
+
+    fn write_value(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let raw = value.as_raw();
+
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_FALSE if value.is_nil() => self.out.push(NIL),
+            sys::mrb_vtype_MRB_TT_FALSE => self.out.push(FALSE),
+            sys::mrb_vtype_MRB_TT_TRUE => self.out.push(TRUE),
+            sys::mrb_vtype_MRB_TT_FIXNUM => {
+                let int = value.as_i64().expect("Fixnum");
+                self.out.push(FIXNUM);
+                write_varint(&mut self.out, ((int << 1) ^ (int >> 63)) as u64);
+            }
+            sys::mrb_vtype_MRB_TT_FLOAT => {
+                self.out.push(FLOAT);
+                self.out.extend_from_slice(&value.as_f64().expect("Float").to_le_bytes());
+            }
+            sys::mrb_vtype_MRB_TT_SYMBOL => self.write_symbol(self.ctx.symbol_bytes(value.as_symbol().expect("Symbol"))),
+            _ => return self.write_object(value),
+        }
+
+        Ok(())
+    }
+
+    fn write_object(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let ptr = value.as_ptr();
+
+        if let Some(index) = self.objects.get(&ptr) {
+            let index = *index;
+            self.out.push(LINK);
+            self.write_len(index);
+            return Ok(());
+        }
+
+        // only values which hold others count towards the limit, the same
+        // as when loading
+        let nested = matches!(value.as_raw().tt,
+            sys::mrb_vtype_MRB_TT_ARRAY | sys::mrb_vtype_MRB_TT_HASH | sys::mrb_vtype_MRB_TT_OBJECT);
+
+        if nested && self.depth == MAX_DEPTH {
+            return Err(self.ctx.core_exception("ArgumentError", &format!("nesting of {} is too deep to dump", MAX_DEPTH + 1)));
+        }
+
+        self.objects.insert(ptr, self.objects.len());
+
+        self.depth += nested as usize;
+        let result = self.write_contents(value);
+        self.depth -= nested as usize;
+        result
+    }
+
+    fn write_ivars(&mut self, ivars: Vec<MrbValue<'mrb>>) -> MrbResult<'mrb, ()> {
+        self.write_len(ivars.len() / 2);
+
+        for pair in ivars.chunks(2) {
+            self.write_symbol(self.ctx.symbol_bytes(pair[0].as_symbol().expect("Symbol")));
+            self.write_value(pair[1])?;
+        }
+
+        Ok(())
+    }
+
+    fn write_contents(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let ctx = self.ctx;
+        let raw = value.as_raw();
+
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_STRING | sys::mrb_vtype_MRB_TT_ARRAY | sys::mrb_vtype_MRB_TT_HASH => {
+                // of these only Hashes can hold instance variables in mruby
+                let ivars = ctx.boundary(|| unsafe { sys::mrbrs_obj_ivars(ctx.mrb, raw) })?;
+                let ivars = ctx.array_entries(unsafe { MrbValue::new(ivars) }).expect("Array");
+
+                if !ivars.is_empty() {
+                    self.out.push(IVARS);
+                }
+
+                let class = unsafe { sys::mrb_obj_class(ctx.mrb, raw) };
+
+                let base = unsafe {
+                    match raw.tt {
+                        sys::mrb_vtype_MRB_TT_STRING => (*ctx.mrb).string_class,
+                        sys::mrb_vtype_MRB_TT_ARRAY => (*ctx.mrb).array_class,
+                        _ => (*ctx.mrb).hash_class,
+                    }
+                };
+
+                if class != base {
+                    let path = self.class_path(class)?;
+                    self.out.push(USER_CLASS);
+                    self.write_symbol(path);
+                }
+
+                self.write_builtin(value)?;
+
+                if !ivars.is_empty() {
+                    self.write_ivars(ivars)?;
+                }
+            }
+            sys::mrb_vtype_MRB_TT_CLASS | sys::mrb_vtype_MRB_TT_MODULE => {
+                let path = self.class_path(value.as_ptr() as *mut sys::RClass)?;
+                self.out.push(CLASS);
+                self.write_symbol(path);
+            }
+            sys::mrb_vtype_MRB_TT_OBJECT | sys::mrb_vtype_MRB_TT_DATA => {
+                let path = self.class_path(unsafe { sys::mrb_obj_class(ctx.mrb, raw) })?;
+
+                if ctx.respond_to(value, "_dump")? {
+                    // -1 is the depth limit Marshal passes when there is none
+                    let data = ctx.funcall(value, "_dump", &[ctx.fixnum_value(-1)])?;
+                    let data = ctx.string_bytes(data)
+                        .ok_or_else(|| ctx.core_exception("TypeError", "_dump() must return String"))?;
+
+                    self.out.push(USER);
+                    self.write_symbol(path);
+                    self.write_bytes(&data);
+                } else if raw.tt == sys::mrb_vtype_MRB_TT_OBJECT {
+                    let ivars = ctx.boundary(|| unsafe { sys::mrbrs_obj_ivars(ctx.mrb, raw) })?;
+                    let ivars = ctx.array_entries(unsafe { MrbValue::new(ivars) }).expect("Array");
+
+                    self.out.push(OBJECT);
+                    self.write_symbol(path);
+                    self.write_ivars(ivars)?;
+                } else {
+                    return Err(ctx.core_exception("TypeError", &format!("can't dump {}", ctx.class_name(value))));
+                }
+            }
+            _ => return Err(ctx.core_exception("TypeError", &format!("can't dump {}", ctx.class_name(value)))),
+        }
+
+        Ok(())
+    }
+
+    // Strings, Arrays and Hashes without their class or instance variables
+    fn write_builtin(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let ctx = self.ctx;
+
+        match value.as_raw().tt {
+            sys::mrb_vtype_MRB_TT_STRING => {
+                self.out.push(STRING);
+                self.write_bytes(&ctx.string_bytes(value).expect("String"));
+            }
+            sys::mrb_vtype_MRB_TT_ARRAY => {
+                let values = ctx.array_entries(value).expect("Array");
+                self.out.push(ARRAY);
+                self.write_len(values.len());
+
+                for value in values {
+                    self.write_value(value)?;
+                }
+            }
+            sys::mrb_vtype_MRB_TT_HASH => {
+                let entries = ctx.hash_entries(value)?;
+                self.out.push(HASH);
+                self.write_len(entries.len());
+
+                for (key, value) in entries {
+                    self.write_value(key)?;
+                    self.write_value(value)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn class_path(&self, class: *mut sys::RClass) -> MrbResult<'mrb, Vec<u8>> {
+        let ctx = self.ctx;
+        let path = ctx.boundary(|| unsafe { sys::mrbrs_class_path(ctx.mrb, class) })?;
+
+        ctx.string_bytes(unsafe { MrbValue::new(path) }).ok_or_else(|| {
+            let class = unsafe { MrbValue::new(sys::mrbrs_obj_value(class as *mut c_void)) };
+            ctx.core_exception("TypeError", &format!("can't dump anonymous class {}", ctx.inspect(class)))
+        })
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+struct Loader<'a, 'mrb, 'b> {
+    ctx: &'a Context<'mrb>,
+    input: &'b [u8],
+
+    // values and symbol names in the order they were read, for links to
+    // refer back to
+    objects: Vec<MrbValue<'mrb>>,
+    symbols: Vec<Vec<u8>>,
+
+    depth: usize,
+}
+
+impl<'a, 'mrb, 'b> Loader<'a, 'mrb, 'b> {
+    fn error(&self, message: &str) -> MrbException<'mrb> {
+        self.ctx.core_exception("ArgumentError", message)
+    }
+
+    fn take(&mut self, len: usize) -> MrbResult<'mrb, &'b [u8]> {
+        if len > self.input.len() {
+            return Err(self.error("marshal data too short"));
+        }
+
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> MrbResult<'mrb, u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn read_varint(&mut self) -> MrbResult<'mrb, u64> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(self.error("invalid dump data: integer too long"))
+    }
+
+    fn read_len(&mut self) -> MrbResult<'mrb, usize> {
+        let len = self.read_varint()?;
+
+        // every element takes at least a byte, so this also stops bogus
+        // lengths from allocating
+        match len.try_into() {
+            Ok(len) if len <= self.input.len() => Ok(len),
+            _ => Err(self.error("marshal data too short")),
+        }
+    }
+
+    fn read_bytes(&mut self) -> MrbResult<'mrb, &'b [u8]> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+
+    fn read_symbol(&mut self) -> MrbResult<'mrb, Vec<u8>> {
+        let tag = self.byte()?;
+        self.read_symbol_tagged(tag)
+    }
+
+    fn read_symbol_tagged(&mut self, tag: u8) -> MrbResult<'mrb, Vec<u8>> {
+        match tag {
+            SYMBOL => {
+                let name = self.read_bytes()?.to_vec();
+                self.symbols.push(name.clone());
+                Ok(name)
+            }
+            SYMBOL_LINK => {
+                let index = self.read_len()?;
+                self.symbols.get(index).cloned().ok_or_else(|| self.error("invalid dump data: bad symbol link"))
+            }
+            _ => Err(self.error("invalid dump data: expected a symbol")),
+        }
+    }
+
+    fn read_class(&mut self) -> MrbResult<'mrb, *mut sys::RClass> {
+        let ctx = self.ctx;
+        let path = self.read_symbol()?;
+
+        ctx.boundary(|| unsafe {
+            sys::mrbrs_path2class(ctx.mrb, path.as_ptr() as *const i8, path.len().try_into().unwrap())
+        })
+    }
+
+    fn read_ivars(&mut self, obj: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let ctx = self.ctx;
+
+        for _ in 0..self.read_len()? {
+            let name = ctx.intern_bytes(&self.read_symbol()?)?;
+            let value = self.read_value()?;
+            ctx.boundary(|| unsafe { sys::mrbrs_iv_set(ctx.mrb, obj.as_raw(), name.as_raw(), value.as_raw()) })?;
+        }
+
+        Ok(())
+    }
+
+    fn register(&mut self, value: MrbValue<'mrb>) -> usize {
+        self.objects.push(value);
+        self.objects.len() - 1
+    }
+
+    // reads the contents of an Array, Hash or Object one level deeper. as in
+    // the dumper, other values don't count towards the limit
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> MrbResult<'mrb, T>) -> MrbResult<'mrb, T> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nesting of {} is too deep to load", MAX_DEPTH + 1)));
+        }
+
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn read_value(&mut self) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let ctx = self.ctx;
+
+        match self.byte()? {
+            NIL => Ok(ctx.nil_value()),
+            TRUE => Ok(ctx.bool_value(true)),
+            FALSE => Ok(ctx.bool_value(false)),
+            FIXNUM => {
+                let zigzag = self.read_varint()?;
+                ctx.new_int((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+            }
+            FLOAT => {
+                let bytes = self.take(8)?;
+                ctx.new_float(f64::from_le_bytes(bytes.try_into().unwrap()))
+            }
+            tag @ SYMBOL | tag @ SYMBOL_LINK => {
+                let name = self.read_symbol_tagged(tag)?;
+                ctx.intern_bytes(&name)
+            }
+            STRING => {
+                let bytes = self.read_bytes()?;
+                let string = ctx.new_string_bytes(bytes)?;
+                self.register(string);
+                Ok(string)
+            }
+            ARRAY => {
+                let array = ctx.new_array()?;
+                self.register(array);
+
+                self.nested(|loader| {
+                    for _ in 0..loader.read_len()? {
+                        let value = loader.read_value()?;
+                        ctx.array_push(array, value)?;
+                    }
+
+                    Ok(array)
+                })
+            }
+            HASH => {
+                let hash = ctx.new_hash()?;
+                self.register(hash);
+
+                self.nested(|loader| {
+                    for _ in 0..loader.read_len()? {
+                        let key = loader.read_value()?;
+                        let value = loader.read_value()?;
+                        ctx.hash_set(hash, key, value)?;
+                    }
+
+                    Ok(hash)
+                })
+            }
+            CLASS => {
+                let class = self.read_class()?;
+                let class = unsafe { MrbValue::new(sys::mrbrs_obj_value(class as *mut c_void)) };
+                self.register(class);
+                Ok(class)
+            }
+            OBJECT => {
+                let class = self.read_class()?;
+                let obj = ctx.boundary(|| unsafe { sys::mrbrs_obj_alloc(ctx.mrb, class) })?;
+                let obj = unsafe { MrbValue::new(obj) };
+                self.register(obj);
+                self.nested(|loader| loader.read_ivars(obj))?;
+                Ok(obj)
+            }
+            IVARS => {
+                // only fresh Hashes take instance variables, never links
+                match self.input.first() {
+                    Some(&HASH) | Some(&USER_CLASS) => {}
+                    _ => return Err(self.error("invalid dump data: bad instance variables")),
+                }
+
+                let obj = self.read_value()?;
+                self.nested(|loader| loader.read_ivars(obj))?;
+                Ok(obj)
+            }
+            USER_CLASS => {
+                let class = self.read_class()?;
+
+                match self.input.first() {
+                    Some(&STRING) | Some(&ARRAY) | Some(&HASH) => {}
+                    _ => return Err(self.error("invalid dump data: bad user class")),
+                }
+
+                let obj = self.read_value()?;
+                ctx.boundary(|| unsafe { sys::mrbrs_obj_set_class(ctx.mrb, obj.as_raw(), class) })?;
+                Ok(obj)
+            }
+            USER => {
+                let class = self.read_class()?;
+                let class = unsafe { MrbValue::new(sys::mrbrs_obj_value(class as *mut c_void)) };
+
+                // the slot is reserved up front to keep later links in step,
+                // and filled in once _load has returned
+                let index = self.register(ctx.nil_value());
+                let data = ctx.new_string_bytes(self.read_bytes()?)?;
+
+                if !ctx.respond_to(class, "_load")? {
+                    return Err(ctx.core_exception("TypeError", &format!("class {} needs to have method `_load'", ctx.inspect(class))));
+                }
+
+                let obj = ctx.funcall(class, "_load", &[data])?;
+                self.objects[index] = obj;
+                Ok(obj)
+            }
+            LINK => {
+                let index = self.read_len()?;
+                self.objects.get(index).copied().ok_or_else(|| self.error("invalid dump data: bad object link"))
+            }
+            tag => Err(self.error(&format!("invalid dump data: unknown tag {:#04x}", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Context, MAX_DEPTH};
+
+    const CLASSES: &str = r##"
+        class Names < Array; end
+        class Text < String; end
+
+        class Attrs < Hash
+            attr_reader :meta
+
+            def tag(meta)
+                @meta = meta
+                self
+            end
+        end
+
+        class Point
+            attr_reader :x, :y
+
+            def initialize(x, y)
+                @x = x
+                @y = y
+            end
+        end
+
+        module Geo
+            class Tag
+                def initialize(name)
+                    @name = name
+                end
+
+                def _dump(level)
+                    @name
+                end
+
+                def self._load(data)
+                    new(data.upcase)
+                end
+
+                def inspect
+                    "#<Tag #{@name}>"
+                end
+            end
+        end
+    "##;
+
+    fn eval(mrb: &Context, code: &str) -> String {
+        mrb.inspect(mrb.load_string(code).unwrap()).into_owned()
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut from = Mrb::open();
+
+        let dump = from.context(|mrb| {
+            mrb.load_string(CLASSES).unwrap();
+
+            let value = mrb.load_string(r#"
+                s = "shared"
+                point = Point.new(1.5, [s, s])
+                h = { point: point, again: point, tag: Geo::Tag.new("t"), class: Geo::Tag, sym: :"odd sym", n: [nil, true, false, -7, 2 ** 40] }
+                attrs = Attrs.new.tag(:m)
+                attrs[:a] = 1
+                h[:sub] = [Names.new << 1, Text.new("t"), attrs]
+                h[:self] = h
+                h
+            "#).unwrap();
+
+            mrb.dump(value).unwrap()
+        });
+
+        // dumps can be loaded into another interpreter with the same classes
+        let mut to = Mrb::open();
+
+        to.context(|mrb| {
+            mrb.load_string(CLASSES).unwrap();
+            mrb.global_set("$h", mrb.load_dump(&dump).unwrap()).unwrap();
+
+            assert_eq!("1.5", eval(mrb, "$h[:point].x"));
+            assert_eq!("[\"shared\", \"shared\"]", eval(mrb, "$h[:point].y"));
+            assert_eq!("true", eval(mrb, "$h[:point].y[0].equal?($h[:point].y[1])"));
+            assert_eq!("true", eval(mrb, "$h[:point].equal?($h[:again])"));
+            assert_eq!("true", eval(mrb, "$h[:self].equal?($h)"));
+            assert_eq!("#<Tag T>", eval(mrb, "$h[:tag]"));
+            assert_eq!("Geo::Tag", eval(mrb, "$h[:class]"));
+            assert_eq!(":\"odd sym\"", eval(mrb, "$h[:sym]"));
+            assert_eq!("[nil, true, false, -7, 1099511627776]", eval(mrb, "$h[:n]"));
+            assert_eq!("[Names, Text, Attrs]", eval(mrb, "$h[:sub].map { |v| v.class }"));
+            assert_eq!("[[1], \"t\", {:a=>1}]", eval(mrb, "$h[:sub]"));
+            assert_eq!(":m", eval(mrb, "$h[:sub][2].meta"));
+        });
+
+        Mrb::open().context(|mrb| {
+            let err = mrb.load_dump(&dump).unwrap_err();
+            assert_eq!("undefined class/module Point (ArgumentError)", format!("{:?}", err));
+
+            let err = mrb.load_dump(&dump[..dump.len() - 1]).unwrap_err();
+            assert_eq!("marshal data too short (ArgumentError)", format!("{:?}", err));
+
+            let err = mrb.load_dump(b"junk").unwrap_err();
+            assert_eq!("incompatible dump format (TypeError)", format!("{:?}", err));
+
+            // instance variable names are checked, as dumps may be untrusted
+            let err = mrb.load_dump(b"MRBD\x01o:\x06Object\x01:\x04name0").unwrap_err();
+            assert!(format!("{:?}", err).ends_with("(NameError)"));
+
+            // only subclasses of the dumped value's class are accepted
+            let err = mrb.load_dump(b"MRBD\x01C:\x06Object\"\x00").unwrap_err();
+            assert_eq!("Object is not a subclass of String (ArgumentError)", format!("{:?}", err));
+
+            let err = mrb.dump(mrb.load_string("[proc { 1 }]").unwrap()).unwrap_err();
+            assert_eq!("can't dump Proc (TypeError)", format!("{:?}", err));
+
+            let err = mrb.dump(mrb.load_string("Class.new.new").unwrap()).unwrap_err();
+            assert!(format!("{:?}", err).starts_with("can't dump anonymous class #<Class:"));
+        });
+    }
+
+    #[test]
+    fn test_dump_nesting() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            mrb.load_string(CLASSES).unwrap();
+
+            // leaves and wrappers don't count towards the limit, only the
+            // Arrays, Hashes and objects holding them
+            let nested = |depth| format!("
+                a = [Point.new(1, {{ s: Text.new('s') }})]
+                {}.times {{ a = [a, 'leaf', :sym, 1.5] }}
+                a
+            ", depth - 3);
+
+            let value = mrb.load_string(&nested(MAX_DEPTH)).unwrap();
+            let loaded = mrb.load_dump(&mrb.dump(value).unwrap()).unwrap();
+            assert_eq!(mrb.inspect(value), mrb.inspect(loaded));
+
+            let value = mrb.load_string(&nested(MAX_DEPTH + 1)).unwrap();
+            let err = mrb.dump(value).unwrap_err();
+            assert_eq!("nesting of 129 is too deep to dump (ArgumentError)", format!("{:?}", err));
+
+            // the loader enforces the same limit on data from elsewhere
+            let mut dump = b"MRBD\x01".to_vec();
+            dump.extend((0..MAX_DEPTH).flat_map(|_| b"[\x01".iter().copied()));
+            dump.extend(b"[\x00");
+            let err = mrb.load_dump(&dump).unwrap_err();
+            assert_eq!("nesting of 129 is too deep to load (ArgumentError)", format!("{:?}", err));
+        });
+    }
+
+    #[test]
+    fn test_define_dump() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            mrb.load_string("class Secret; attr_reader :value; def initialize(value); @value = value; end; end").unwrap();
+            let secret = mrb.class_get("Secret").unwrap().unwrap();
+
+            mrb.define_dump(secret, |ctx, obj| {
+                let value = ctx.funcall(obj, "value", &[])?;
+                Ok(ctx.string_bytes(value).unwrap().into_iter().rev().collect())
+            }, |ctx, class, bytes| {
+                let value = ctx.new_string_bytes(&bytes.iter().rev().copied().collect::<Vec<_>>())?;
+                ctx.funcall(class, "new", &[value])
+            }).unwrap();
+
+            let dump = mrb.dump(mrb.load_string("[Secret.new('hunter2')]").unwrap()).unwrap();
+            assert!(!dump.windows(7).any(|window| window == b"hunter2"));
+
+            mrb.global_set("$loaded", mrb.load_dump(&dump).unwrap()).unwrap();
+            assert_eq!("\"hunter2\"", eval(mrb, "$loaded[0].value"));
+        });
+    }
+}
@@ -0,0 +1,223 @@
+use std::os::raw::c_void;
+
+use serde_json::{Map, Number, Value};
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult, MAX_DEPTH};
+use crate::numeric::fixable;
+use crate::object::MrbValue;
+
+impl<'mrb> Context<'mrb> {
+    /// Parses JSON text into Ruby values. Objects become Hashes with String
+    /// keys, and integers outside the Fixnum range become Floats. Invalid
+    /// JSON raises `ArgumentError`.
+    pub fn from_json(&self, json: &str) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let value = serde_json::from_str(json)
+            .map_err(|err| self.core_exception("ArgumentError", &format!("invalid JSON: {}", err)))?;
+
+        self.from_json_value(&value)
+    }
+
+    /// Generates JSON text for `value`, as described in `to_json_value`.
+    pub fn to_json(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, String> {
+        let value = self.to_json_value(value)?;
+        Ok(value.to_string())
+    }
+
+    /// Converts a parsed JSON value to Ruby values, as described in
+    /// `from_json`. Values nested too deeply raise `ArgumentError`.
+    pub fn from_json_value(&self, value: &Value) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        self.json_to_value(value, 0)
+    }
+
+    fn json_to_value(&self, value: &Value, depth: usize) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let nested = value.is_array() || value.is_object();
+
+        if nested && depth == MAX_DEPTH {
+            return Err(self.core_exception("ArgumentError", &format!("nesting of {} is too deep for JSON", MAX_DEPTH + 1)));
+        }
+
+        match value {
+            Value::Null => Ok(self.nil_value()),
+            Value::Bool(value) => Ok(self.bool_value(*value)),
+            Value::Number(number) => match number.as_i64().filter(|number| fixable(*number)) {
+                Some(number) => Ok(self.fixnum_value(number)),
+                None => self.new_float(number.as_f64().expect("f64")),
+            },
+            Value::String(string) => self.new_string(string),
+            Value::Array(values) => {
+                let array = self.new_array()?;
+
+                for value in values {
+                    self.array_push(array, self.json_to_value(value, depth + 1)?)?;
+                }
+
+                Ok(array)
+            }
+            Value::Object(map) => {
+                let hash = self.new_hash()?;
+
+                for (key, value) in map {
+                    self.hash_set(hash, self.new_string(key)?, self.json_to_value(value, depth + 1)?)?;
+                }
+
+                Ok(hash)
+            }
+        }
+    }
+
+    /// Converts `value` to JSON. Symbols become strings, and Hash keys may
+    /// be Strings, Symbols or numbers, which are converted to strings. Other
+    /// keys and values raise `TypeError`, NaN and Infinity raise
+    /// `RangeError`, and cyclic or too deeply nested structures raise
+    /// `ArgumentError`.
+    pub fn to_json_value(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, Value> {
+        self.json_value(value, &mut Vec::new())
+    }
+
+    // `parents` holds the Arrays and Hashes enclosing `value`
+    fn json_value(&self, value: MrbValue<'mrb>, parents: &mut Vec<*mut c_void>) -> MrbResult<'mrb, Value> {
+        let raw = value.as_raw();
+
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_FALSE if value.is_nil() => Ok(Value::Null),
+            sys::mrb_vtype_MRB_TT_FALSE => Ok(Value::Bool(false)),
+            sys::mrb_vtype_MRB_TT_TRUE => Ok(Value::Bool(true)),
+            sys::mrb_vtype_MRB_TT_FIXNUM => Ok(Value::Number(value.as_i64().expect("Fixnum").into())),
+            sys::mrb_vtype_MRB_TT_FLOAT => {
+                let float = value.as_f64().expect("Float");
+
+                Number::from_f64(float).map(Value::Number).ok_or_else(|| {
+                    let name = if float.is_nan() {
+                        "NaN"
+                    } else if float > 0.0 {
+                        "Infinity"
+                    } else {
+                        "-Infinity"
+                    };
+
+                    self.core_exception("RangeError", &format!("{} is not allowed in JSON", name))
+                })
+            }
+            sys::mrb_vtype_MRB_TT_SYMBOL => Ok(Value::String(self.symbol_name(value.as_symbol().expect("Symbol")))),
+            sys::mrb_vtype_MRB_TT_STRING => self.json_string(value).map(Value::String),
+            sys::mrb_vtype_MRB_TT_ARRAY | sys::mrb_vtype_MRB_TT_HASH => {
+                let ptr = value.as_ptr();
+
+                if parents.contains(&ptr) {
+                    return Err(self.core_exception("ArgumentError", "circular reference detected in JSON conversion"));
+                }
+
+                if parents.len() == MAX_DEPTH {
+                    return Err(self.core_exception("ArgumentError", &format!("nesting of {} is too deep for JSON", MAX_DEPTH + 1)));
+                }
+
+                parents.push(ptr);
+                let result = self.json_container(value, parents);
+                parents.pop();
+                result
+            }
+            _ => Err(self.core_exception("TypeError", &format!("{} can't be converted to JSON", self.inspect(value)))),
+        }
+    }
+
+    fn json_container(&self, value: MrbValue<'mrb>, parents: &mut Vec<*mut c_void>) -> MrbResult<'mrb, Value> {
+        if let Some(values) = self.array_entries(value) {
+            return values.into_iter()
+                .map(|value| self.json_value(value, parents))
+                .collect::<Result<_, _>>()
+                .map(Value::Array);
+        }
+
+        let mut map = Map::new();
+
+        for (key, value) in self.hash_entries(value)? {
+            map.insert(self.json_key(key)?, self.json_value(value, parents)?);
+        }
+
+        Ok(Value::Object(map))
+    }
+
+    fn json_key(&self, key: MrbValue<'mrb>) -> MrbResult<'mrb, String> {
+        match key.as_raw().tt {
+            sys::mrb_vtype_MRB_TT_STRING => self.json_string(key),
+            sys::mrb_vtype_MRB_TT_SYMBOL => Ok(self.symbol_name(key.as_symbol().expect("Symbol"))),
+            sys::mrb_vtype_MRB_TT_FIXNUM | sys::mrb_vtype_MRB_TT_FLOAT => Ok(self.inspect(key).into_owned()),
+            _ => Err(self.core_exception("TypeError", &format!("Hash key {} can't be converted to JSON", self.inspect(key)))),
+        }
+    }
+
+    fn json_string(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, String> {
+        String::from_utf8(self.string_bytes(value).expect("String"))
+            .map_err(|_| self.core_exception("TypeError", "String with invalid UTF-8 can't be converted to JSON"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{Mrb, Context};
+
+    fn to_json(mrb: &Context, code: &str) -> Result<String, String> {
+        let value = mrb.load_string(code).unwrap();
+        mrb.to_json(value).map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            assert_eq!(
+                r#"{"name":"web","ports":[80,443],"ratio":0.5,"tls":true,"mode":"fast","1":null,"2.5":false}"#,
+                to_json(mrb, "{ name: 'web', ports: [80, 443], ratio: 0.5, tls: true, :mode => :fast, 1 => nil, 2.5 => false }").unwrap(),
+            );
+
+            assert_eq!("Hash key [1] can't be converted to JSON (TypeError)", to_json(mrb, "{ [1] => 2 }").unwrap_err());
+            assert_eq!("1..2 can't be converted to JSON (TypeError)", to_json(mrb, "[1..2]").unwrap_err());
+            assert_eq!("NaN is not allowed in JSON (RangeError)", to_json(mrb, "0.0 / 0").unwrap_err());
+            assert_eq!("-Infinity is not allowed in JSON (RangeError)", to_json(mrb, "[-1.0 / 0]").unwrap_err());
+
+            assert_eq!(
+                "circular reference detected in JSON conversion (ArgumentError)",
+                to_json(mrb, "a = [1]; h = { a: a }; a << h; h").unwrap_err(),
+            );
+
+            assert_eq!(
+                "nesting of 129 is too deep for JSON (ArgumentError)",
+                to_json(mrb, "a = []; 1000.times { a = [a] }; a").unwrap_err(),
+            );
+
+            // the same value may appear more than once, as long as it doesn't
+            // contain itself
+            assert_eq!("[[1],[1]]", to_json(mrb, "a = [1]; [a, a]").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_from_json() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let value = mrb.from_json(r#"{"name": "web", "ports": [80, 1e2], "opts": {"tls": null}}"#).unwrap();
+            assert_eq!(r#"{"name"=>"web", "ports"=>[80, 100.0], "opts"=>{"tls"=>nil}}"#, mrb.inspect(value));
+
+            let value = mrb.from_json("18446744073709551615").unwrap();
+            mrb.global_set("$big", value).unwrap();
+            assert_eq!("true", mrb.inspect(mrb.load_string("$big.is_a?(Float) && $big > 0").unwrap()));
+
+            let err = mrb.from_json("{").unwrap_err();
+            assert_eq!("invalid JSON: EOF while parsing an object at line 1 column 1 (ArgumentError)", format!("{:?}", err));
+
+            let value = mrb.from_json_value(&json!({ "a": [true, 1.5] })).unwrap();
+            assert_eq!(json!({ "a": [true, 1.5] }), mrb.to_json_value(value).unwrap());
+
+            // values built in Rust aren't bound by the parser's own limit
+            let deep = (0..200).fold(json!(1), |value, _| json!([value]));
+            let err = mrb.from_json_value(&deep).unwrap_err();
+            assert_eq!("nesting of 129 is too deep for JSON (ArgumentError)", format!("{:?}", err));
+        });
+    }
+}
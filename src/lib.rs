@@ -2,40 +2,177 @@ pub use mrb_sys as sys;
 
 use std::borrow::Cow;
 use std::convert::TryInto;
-use std::ffi::CString;
+use std::error;
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Display};
 use std::os::raw::c_int;
+use std::rc::Rc;
 use std::slice;
 
+mod alloc;
 mod boundary;
+mod builder;
+mod compile;
+mod dump;
+#[cfg(feature = "serde_json")]
+mod json;
+mod limits;
 mod marker;
 mod method;
+#[cfg(feature = "rmp")]
+mod msgpack;
+mod numeric;
 mod object;
+mod output;
+mod require;
+mod resolver;
+mod sandbox;
+mod session;
 mod state;
-
-pub use object::{MrbValue, MrbObject, MrbClass, MrbException};
+mod string;
+mod symbol;
+mod transfer;
+
+#[cfg(feature = "log")]
+mod logger;
+#[cfg(feature = "repl")]
+pub mod repl;
+#[cfg(feature = "serde")]
+mod de;
+#[cfg(feature = "serde")]
+mod ser;
+
+pub use alloc::MemoryStats;
+pub use builder::MrbBuilder;
+pub use compile::{Diagnostic, Severity};
+pub use limits::{Abort, InterruptHandle};
+pub use object::{MrbValue, MrbSymbol, MrbObject, MrbClass, MrbException};
+pub use resolver::{Module, ModuleResolver, DirectoryResolver, MemoryResolver};
+#[cfg(feature = "include_dir")]
+pub use resolver::EmbeddedResolver;
+#[cfg(feature = "rmp")]
+pub use msgpack::MSGPACK_SYMBOL_EXT;
+pub use sandbox::SandboxPolicy;
+#[cfg(feature = "serde")]
+pub use de::from_value;
+#[cfg(feature = "serde")]
+pub use ser::{KeyStyle, to_value, to_value_with};
+pub use session::{Input, Session};
 
 use object::MrbPtr;
+use output::Output;
 use marker::Invariant;
 use state::MrbState;
 
+// conversions between Ruby values and other formats recurse once for each
+// level of nesting, so they give up past this depth rather than overflow the
+// stack. this is the same limit serde_json puts on parsing
+const MAX_DEPTH: usize = 128;
+
 pub struct Mrb {
     state: MrbState,
+    output: Option<Rc<Output>>,
+    sandbox: Option<SandboxPolicy>,
 }
 
 impl Mrb {
     pub fn open() -> Self {
-        let state = MrbState::open().expect("MrbState::open");
-        Mrb { state }
+        Mrb::builder().open().expect("MrbBuilder::open")
+    }
+
+    pub fn builder() -> MrbBuilder {
+        MrbBuilder::new()
+    }
+
+    /// Opens an interpreter with the default `SandboxPolicy` applied.
+    pub fn sandboxed() -> Result<Self, Error> {
+        Mrb::builder().sandbox(SandboxPolicy::default()).open()
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.state.allocator().stats()
     }
 
     pub fn context<Ret>(&mut self, f: impl for<'mrb> FnOnce(&Context<'mrb>) -> Ret) -> Ret {
+        self.state.limits().reset();
         let ctx = unsafe { Context::new(self.state.as_ptr()) };
         f(&ctx)
     }
 
-    pub fn try_context<Ret>(&mut self, f: impl for<'mrb> FnOnce(&Context<'mrb>) -> MrbResult<'mrb, Ret>) -> Result<Ret, String> {
+    /// Like `context`, but an exception escaping `f` is returned as an
+    /// `Error`. This used to be the inspected exception as a `String`, which
+    /// `Error` still converts into, but aborts are now told apart by
+    /// `Error::Aborted`.
+    pub fn try_context<Ret>(&mut self, f: impl for<'mrb> FnOnce(&Context<'mrb>) -> MrbResult<'mrb, Ret>) -> Result<Ret, Error> {
+        self.state.limits().reset();
         let ctx = unsafe { Context::new(self.state.as_ptr()) };
-        f(&ctx).map_err(|e| format!("{:?}", e))
+        f(&ctx).map_err(Error::from)
+    }
+
+    pub fn compile(&mut self, code: &str, filename: &str) -> Result<Vec<u8>, Error> {
+        self.context(|ctx| ctx.compile(code, filename))
+    }
+
+    /// Returns a handle to interrupt running scripts from other threads.
+    /// Fails with `Error::Unsupported` when mruby lacks the code fetch hook,
+    /// which is needed to stop scripts that never leave Ruby.
+    pub fn interrupt_handle(&self) -> Result<InterruptHandle, Error> {
+        if !limits::supported() {
+            return Err(Error::Unsupported("interrupts require mruby built with MRB_ENABLE_DEBUG_HOOK"));
+        }
+
+        Ok(self.state.limits().interrupt_handle())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// An exception escaped `try_context`. Holds the inspected exception.
+    Exception(String),
+    /// The script was stopped by an execution limit or an interrupt.
+    Aborted(Abort),
+    /// The parser rejected the script.
+    Syntax(Vec<Diagnostic>),
+    /// A feature was asked for which this build of mruby can't provide.
+    Unsupported(&'static str),
+    /// mruby failed to boot, or needed more memory than the limit allows.
+    Open,
+}
+
+impl<'mrb> From<MrbException<'mrb>> for Error {
+    fn from(e: MrbException<'mrb>) -> Self {
+        match e.abort() {
+            Some(abort) => Error::Aborted(abort),
+            None => Error::Exception(format!("{:?}", e)),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Exception(message) => write!(f, "{}", message),
+            Error::Aborted(Abort::Timeout) => write!(f, "execution timed out"),
+            Error::Aborted(Abort::InstructionLimit) => write!(f, "instruction limit exceeded"),
+            Error::Aborted(Abort::Interrupted) => write!(f, "execution interrupted"),
+            Error::Syntax(diagnostics) => {
+                let messages = diagnostics.iter()
+                    .map(|diagnostic| diagnostic.to_string())
+                    .collect::<Vec<_>>();
+
+                write!(f, "{}", messages.join("\n"))
+            }
+            Error::Unsupported(message) => write!(f, "{}", message),
+            Error::Open => write!(f, "failed to open mruby state"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
     }
 }
 
@@ -120,6 +257,96 @@ impl<'mrb> Context<'mrb> {
         Ok(unsafe { MrbValue::new(result) })
     }
 
+    /// Like `load_string`, but errors and backtraces report `filename` with
+    /// line numbers counting from `first_line`.
+    pub fn load_string_with_filename(&self, code: &str, filename: &str, first_line: u16) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let filename = CString::new(filename).expect("CString::from");
+
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_load_nstring_filename(
+                self.mrb,
+                code.as_ptr() as *const i8,
+                code.len().try_into().unwrap(),
+                filename.as_ptr(),
+                first_line,
+            )
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    pub fn nil_value(&self) -> MrbValue<'mrb> {
+        unsafe { MrbValue::new(sys::mrbrs_nil_value()) }
+    }
+
+    pub fn bool_value(&self, value: bool) -> MrbValue<'mrb> {
+        unsafe { MrbValue::new(sys::mrbrs_bool_value(value)) }
+    }
+
+    // `value` must fit in a Fixnum, see `new_int` for a checked version
+    pub(crate) fn fixnum_value(&self, value: i64) -> MrbValue<'mrb> {
+        unsafe { MrbValue::new(sys::mrbrs_fixnum_value(value)) }
+    }
+
+    /// The filename and line of the innermost Ruby code on the call stack,
+    /// if it has debug info.
+    pub(crate) fn caller_location(&self) -> Option<(String, u32)> {
+        unsafe {
+            let mut line: i32 = 0;
+            let ptr = sys::mrbrs_caller_location(self.mrb, &mut line as *mut _);
+
+            if ptr.is_null() {
+                None
+            } else {
+                let filename = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                Some((filename, line.try_into().unwrap_or(0)))
+            }
+        }
+    }
+
+    /// A copy of the contents of a String, or None for other values. Unlike
+    /// `MrbValue::as_bytes` this stays valid when the String is modified.
+    pub fn string_bytes(&self, value: MrbValue<'mrb>) -> Option<Vec<u8>> {
+        // copied straight away, before anything can touch the String
+        unsafe { value.as_bytes() }.map(<[u8]>::to_vec)
+    }
+
+    pub(crate) fn as_string(&self, value: MrbValue<'mrb>) -> Option<String> {
+        self.string_bytes(value).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    pub(crate) fn class_name(&self, value: MrbValue<'mrb>) -> String {
+        unsafe {
+            let name = sys::mrb_obj_classname(self.mrb, value.as_raw());
+            CStr::from_ptr(name).to_string_lossy().into_owned()
+        }
+    }
+
+    // the elements of an Array, or None for other values
+    pub(crate) fn array_entries(&self, value: MrbValue<'mrb>) -> Option<Vec<MrbValue<'mrb>>> {
+        unsafe {
+            let mut len: sys::size_t = 0;
+            let ptr = sys::mrbrs_ary_ptr(value.as_raw(), &mut len as *mut _);
+
+            if ptr.is_null() {
+                return None;
+            }
+
+            let values = slice::from_raw_parts(ptr, len.try_into().unwrap());
+            Some(values.iter().map(|value| MrbValue::new(*value)).collect())
+        }
+    }
+
+    // the key/value pairs of a Hash, in insertion order
+    pub(crate) fn hash_entries(&self, hash: MrbValue<'mrb>) -> MrbResult<'mrb, Vec<(MrbValue<'mrb>, MrbValue<'mrb>)>> {
+        let entries = self.boundary(|| unsafe {
+            sys::mrbrs_hash_entries(self.mrb, hash.as_raw())
+        })?;
+
+        let entries = self.array_entries(unsafe { MrbValue::new(entries) }).expect("Array");
+        Ok(entries.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+    }
+
     pub fn new_string(&self, string: &str) -> MrbResult<'mrb, MrbValue<'mrb>> {
         let result = self.boundary(|| unsafe {
             sys::mrbrs_str_new(
@@ -132,21 +359,33 @@ impl<'mrb> Context<'mrb> {
         Ok(unsafe { MrbValue::new(result) })
     }
 
-    pub fn new_string_static(&self, string: &'static str) -> MrbResult<'mrb, MrbValue<'mrb>> {
+    /// Creates a String from arbitrary bytes, which needn't be valid UTF-8.
+    pub fn new_string_bytes(&self, bytes: &[u8]) -> MrbResult<'mrb, MrbValue<'mrb>> {
         let result = self.boundary(|| unsafe {
-            sys::mrbrs_str_new_static(
+            sys::mrbrs_str_new(
                 self.mrb,
-                string.as_ptr() as *const i8,
-                string.len().try_into().unwrap(),
+                bytes.as_ptr() as *const i8,
+                bytes.len().try_into().unwrap(),
             )
         })?;
 
         Ok(unsafe { MrbValue::new(result) })
     }
 
-    pub fn intern(&self, string: &str) -> MrbResult<'mrb, MrbValue<'mrb>> {
+    /// Converts `value` to a String by calling its `to_s` method. Strings
+    /// are returned as is, and `to_s` returning something other than a
+    /// String falls back to the default `#<Class>` form.
+    pub fn to_s(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_obj_as_string(self.mrb, value.as_raw())
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    pub fn new_string_static(&self, string: &'static str) -> MrbResult<'mrb, MrbValue<'mrb>> {
         let result = self.boundary(|| unsafe {
-            sys::mrbrs_intern(
+            sys::mrbrs_str_new_static(
                 self.mrb,
                 string.as_ptr() as *const i8,
                 string.len().try_into().unwrap(),
@@ -156,6 +395,14 @@ impl<'mrb> Context<'mrb> {
         Ok(unsafe { MrbValue::new(result) })
     }
 
+    pub fn intern(&self, string: &str) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        self.intern_bytes(string.as_bytes())
+    }
+
+    pub(crate) fn intern_bytes(&self, bytes: &[u8]) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        self.symbol_from_bytes(bytes).map(MrbValue::from)
+    }
+
     pub fn intern_static(&self, string: &'static str) -> MrbResult<'mrb, MrbValue<'mrb>> {
         let result = self.boundary(|| unsafe {
             sys::mrbrs_intern_static(
@@ -182,6 +429,97 @@ impl<'mrb> Context<'mrb> {
         })
     }
 
+    pub fn new_array(&self) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_ary_new(self.mrb)
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    pub fn array_push(&self, array: MrbValue<'mrb>, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        self.boundary(|| unsafe {
+            sys::mrbrs_ary_push(self.mrb, array.as_raw(), value.as_raw());
+        })
+    }
+
+    pub fn define_global_const(&self, name: &str, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let name = CString::new(name).expect("CString::from");
+
+        self.boundary(|| unsafe {
+            sys::mrbrs_define_global_const(self.mrb, name.as_ptr(), value.as_raw());
+        })
+    }
+
+    pub fn global_get(&self, name: &str) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let name = CString::new(name).expect("CString::from");
+
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_gv_get(self.mrb, name.as_ptr())
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    pub fn global_set(&self, name: &str, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let name = CString::new(name).expect("CString::from");
+
+        self.boundary(|| unsafe {
+            sys::mrbrs_gv_set(self.mrb, name.as_ptr(), value.as_raw());
+        })
+    }
+
+    /// Creates an exception for a method defined in Rust to return as its
+    /// error. If the exception can't be created, the error raised while
+    /// trying is returned instead.
+    pub fn new_exception(&self, class: MrbClass<'mrb>, message: &str) -> MrbException<'mrb> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_exc_new(
+                self.mrb,
+                class.0.as_ptr(),
+                message.as_ptr() as *const i8,
+                message.len().try_into().unwrap(),
+            )
+        });
+
+        match result {
+            Ok(ptr) => MrbException(unsafe { MrbPtr::new(self.mrb, ptr) }),
+            Err(exc) => exc,
+        }
+    }
+
+    // for raising the core exception classes, which always exist
+    pub(crate) fn core_exception(&self, class: &str, message: &str) -> MrbException<'mrb> {
+        match self.class_get(class) {
+            Ok(found) => self.new_exception(found.expect(class), message),
+            Err(exc) => exc,
+        }
+    }
+
+    pub(crate) fn funcall(&self, recv: MrbValue<'mrb>, name: &str, args: &[MrbValue<'mrb>]) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let mid = self.symbol(name)?;
+
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_funcall(
+                self.mrb,
+                recv.as_raw(),
+                mid.as_raw(),
+                args.len().try_into().unwrap(),
+                args.as_ptr() as *const sys::mrb_value,
+            )
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    pub(crate) fn respond_to(&self, value: MrbValue<'mrb>, name: &str) -> MrbResult<'mrb, bool> {
+        let mid = self.symbol(name)?;
+
+        self.boundary(|| unsafe {
+            sys::mrbrs_respond_to(self.mrb, value.as_raw(), mid.as_raw())
+        })
+    }
+
     pub fn equal(&self, a: MrbValue<'mrb>, b: MrbValue<'mrb>) -> MrbResult<'mrb, bool> {
         self.boundary(|| unsafe {
             sys::mrbrs_equal(self.mrb, a.as_raw(), b.as_raw())
@@ -239,6 +577,38 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_load_string_with_filename() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let val = mrb.load_string_with_filename("\n[__FILE__, __LINE__]", "script.rb", 10).unwrap();
+            assert_eq!("[\"script.rb\", 11]", mrb.inspect(val));
+
+            let val = mrb.load_string_with_filename(r#"
+                begin
+                    raise 'hello'
+                rescue => e
+                    e.backtrace.first
+                end
+            "#, "script.rb", 1).unwrap();
+            assert!(mrb.inspect(val).starts_with("\"script.rb:3"));
+        });
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string_with_filename("def foo\n  raise 'hello'\nend\nfoo\n", "script.rb", 1).unwrap_err();
+            let backtrace = err.backtrace();
+
+            assert!(backtrace[0].starts_with("script.rb:2"));
+            assert!(backtrace.iter().any(|line| line.starts_with("script.rb:4")));
+        });
+    }
+
     #[test]
     fn test_string() {
         let mut mrb = Mrb::open();
@@ -249,6 +619,20 @@ mod tests {
 
             let s = mrb.new_string_static("A static string").unwrap();
             assert_eq!("\"A static string\"", mrb.inspect(s).to_string());
+            assert_eq!(Some("A static string"), unsafe { s.as_str() });
+
+            let s = mrb.new_string_bytes(b"caf\xc3\xa9 \xff").unwrap();
+            assert_eq!(Some(&b"caf\xc3\xa9 \xff"[..]), unsafe { s.as_bytes() });
+            assert_eq!(None, unsafe { s.as_str() });
+            assert_eq!(Some(b"caf\xc3\xa9 \xff".to_vec()), mrb.string_bytes(s));
+            assert_eq!(None, mrb.string_bytes(mrb.nil_value()));
+
+            let s = mrb.to_s(mrb.load_string("[1, :two]").unwrap()).unwrap();
+            assert_eq!(Some(&b"[1, :two]"[..]), mrb.string_bytes(s).as_deref());
+            assert_eq!(Some(&b""[..]), mrb.string_bytes(mrb.to_s(mrb.nil_value()).unwrap()).as_deref());
+
+            let err = mrb.to_s(mrb.load_string("Class.new { def to_s; raise 'no'; end }.new").unwrap()).unwrap_err();
+            assert_eq!("no (RuntimeError)", format!("{:?}", err));
         })
     }
 
@@ -281,6 +665,55 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_array() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let array = mrb.new_array().unwrap();
+            assert_eq!("[]", mrb.inspect(array).to_string());
+
+            mrb.array_push(array, mrb.new_string("A").unwrap()).unwrap();
+            mrb.array_push(array, mrb.intern("b").unwrap()).unwrap();
+            assert_eq!("[\"A\", :b]", mrb.inspect(array).to_string());
+
+            let err = mrb.array_push(mrb.new_hash().unwrap(), array).unwrap_err();
+            assert_eq!("expected Array (TypeError)", format!("{:?}", err));
+
+            mrb.define_global_const("LIST", array).unwrap();
+            assert_eq!("[\"A\", :b]", eval(mrb, "LIST").unwrap());
+        })
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            assert_eq!("nil", mrb.inspect(mrb.global_get("$foo").unwrap()));
+
+            mrb.global_set("$foo", mrb.new_string("bar").unwrap()).unwrap();
+            assert_eq!("\"bar\"", eval(mrb, "$foo").unwrap());
+        })
+    }
+
+    #[test]
+    fn test_new_exception() {
+        let mut mrb = Mrb::open();
+
+        mrb.try_context(|mrb| {
+            mrb.define_method(mrb.object_class(), "my_method", |ctx, _self| {
+                let class = ctx.class_get("ArgumentError")?.unwrap();
+                Err(ctx.new_exception(class, "bad argument"))
+            })?;
+
+            assert_eq!("bad argument (ArgumentError)", eval(mrb, "my_method").unwrap_err());
+            assert_eq!("\"rescued\"", eval(mrb, "begin; my_method; rescue ArgumentError; 'rescued'; end").unwrap());
+
+            Ok(())
+        }).expect("try_context");
+    }
+
     #[test]
     fn test_equal() {
         let mut mrb = Mrb::open();
@@ -0,0 +1,328 @@
+use std::cell::Cell;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::object::MrbException;
+
+// reading the clock on every instruction would dominate the cost of the
+// hook, so the deadline is only checked every so often. must be a power of 2
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// Reason a script was forcibly stopped. Aborts are raised as exceptions
+/// that Ruby code cannot rescue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Abort {
+    Timeout,
+    InstructionLimit,
+    Interrupted,
+}
+
+/// Interrupts the interpreter it was created from. Can be sent to and used
+/// from any thread.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle {
+    pending: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Aborts the running script at its next safe point. If no script is
+    /// running, the next one to run is aborted instead.
+    pub fn interrupt(&self) {
+        self.pending.store(true, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct Limits {
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+    instructions: Cell<u64>,
+    deadline: Cell<Option<Instant>>,
+
+    // set from other threads by InterruptHandle. once we've seen it we latch
+    // the interrupt locally so that it keeps firing until the script has
+    // unwound all the way out, even through ensure blocks
+    pending_interrupt: Arc<AtomicBool>,
+    interrupted: Cell<bool>,
+}
+
+impl Limits {
+    pub fn new(instruction_limit: Option<u64>, timeout: Option<Duration>) -> Self {
+        Limits {
+            instruction_limit,
+            timeout,
+            instructions: Cell::new(0),
+            deadline: Cell::new(None),
+            pending_interrupt: Arc::new(AtomicBool::new(false)),
+            interrupted: Cell::new(false),
+        }
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            pending: self.pending_interrupt.clone(),
+        }
+    }
+
+    // limits apply to each call to Mrb::context, so they are reset whenever
+    // we enter the interpreter from the top
+    pub fn reset(&self) {
+        self.instructions.set(0);
+        // a timeout too far off to represent can never be reached
+        self.deadline.set(self.timeout.and_then(|timeout| Instant::now().checked_add(timeout)));
+        self.interrupted.set(false);
+    }
+
+    // called from the code fetch hook for every instruction executed
+    fn tick(&self) -> Option<Abort> {
+        let instructions = self.instructions.get() + 1;
+        self.instructions.set(instructions);
+
+        if self.check_interrupt() {
+            return Some(Abort::Interrupted);
+        }
+
+        if let Some(limit) = self.instruction_limit {
+            if instructions > limit {
+                return Some(Abort::InstructionLimit);
+            }
+        }
+
+        if instructions & (DEADLINE_CHECK_INTERVAL - 1) == 0 {
+            self.check_deadline()
+        } else {
+            None
+        }
+    }
+
+    // called at safe points that don't execute bytecode, such as calls into
+    // Rust defined methods, so time spent in Rust is accounted for too
+    fn check(&self) -> Option<Abort> {
+        if self.check_interrupt() {
+            return Some(Abort::Interrupted);
+        }
+
+        if let Some(limit) = self.instruction_limit {
+            if self.instructions.get() > limit {
+                return Some(Abort::InstructionLimit);
+            }
+        }
+
+        self.check_deadline()
+    }
+
+    fn check_interrupt(&self) -> bool {
+        // only pay for the atomic swap once an interrupt has actually arrived
+        if self.pending_interrupt.load(Ordering::Relaxed) && self.pending_interrupt.swap(false, Ordering::SeqCst) {
+            self.interrupted.set(true);
+        }
+
+        self.interrupted.get()
+    }
+
+    fn check_deadline(&self) -> Option<Abort> {
+        match self.deadline.get() {
+            Some(deadline) if Instant::now() >= deadline => Some(Abort::Timeout),
+            _ => None,
+        }
+    }
+}
+
+/// Whether execution limits can be enforced, which needs mruby's code fetch
+/// hook to run checks while bytecode executes.
+pub(crate) fn supported() -> bool {
+    unsafe { mrb_sys::mrbrs_has_code_fetch_hook() }
+}
+
+unsafe fn carrier(ud: *const mrb_sys::mrbrs_ud, abort: Abort) -> *mut mrb_sys::RObject {
+    match abort {
+        Abort::Timeout => (*ud).timeout_carrier,
+        Abort::InstructionLimit => (*ud).instruction_limit_carrier,
+        Abort::Interrupted => (*ud).interrupt_carrier,
+    }
+}
+
+unsafe fn limits<'a>(mrb: *mut mrb_sys::mrb_state) -> Option<&'a Limits> {
+    let ud = mrb_sys::mrbrs_get_ud(mrb);
+    ((*ud).limits as *const Limits).as_ref()
+}
+
+/// Safety: takes raw pointer. Returns the carrier exception to raise if the
+/// running script has exceeded one of its limits.
+pub(crate) unsafe fn check(mrb: *mut mrb_sys::mrb_state) -> Option<*mut mrb_sys::RObject> {
+    let ud = mrb_sys::mrbrs_get_ud(mrb);
+    let abort = limits(mrb)?.check()?;
+    Some(carrier(ud, abort))
+}
+
+#[no_mangle]
+unsafe extern "C" fn mrbrs_limits_check(mrb: *mut mrb_sys::mrb_state) -> *mut mrb_sys::RObject {
+    // this is called for every instruction, so it must not panic
+    let ud = mrb_sys::mrbrs_get_ud(mrb);
+
+    match limits(mrb).and_then(Limits::tick) {
+        Some(abort) => carrier(ud, abort),
+        None => ptr::null_mut(),
+    }
+}
+
+impl<'mrb> MrbException<'mrb> {
+    /// Returns the reason this exception was raised if the script was
+    /// aborted by an execution limit rather than by an ordinary exception.
+    pub fn abort(&self) -> Option<Abort> {
+        unsafe {
+            let ud = mrb_sys::mrbrs_get_ud(self.0.mrb());
+            let ptr = self.0.as_ptr();
+
+            [Abort::Timeout, Abort::InstructionLimit, Abort::Interrupted]
+                .iter()
+                .cloned()
+                .find(|abort| carrier(ud, *abort) == ptr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::{Mrb, Abort, Error};
+
+    use super::{supported, Limits};
+
+    #[test]
+    fn test_unsupported() {
+        let result = Mrb::builder().timeout(Duration::from_secs(1)).open();
+        assert_eq!(supported(), result.is_ok());
+
+        if !supported() {
+            // refused up front, as pure Ruby loops couldn't be stopped
+            let err = Mrb::builder().instruction_limit(1).open().err().unwrap();
+            assert_eq!(Error::Unsupported("execution limits require mruby built with MRB_ENABLE_DEBUG_HOOK"), err);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "debug-hook")]
+    fn test_debug_hook() {
+        assert!(supported());
+
+        let mut mrb = Mrb::builder()
+            .instruction_limit(10_000)
+            .timeout(Duration::from_secs(5))
+            .open()
+            .unwrap();
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string("loop {}").unwrap_err();
+            assert_eq!(Some(Abort::InstructionLimit), err.abort());
+        });
+    }
+
+    #[test]
+    fn test_timeout() {
+        if !supported() {
+            return;
+        }
+
+        let mut mrb = Mrb::builder()
+            .timeout(Duration::from_millis(50))
+            .open()
+            .unwrap();
+
+        let started = Instant::now();
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string(r#"
+                begin
+                    loop {}
+                rescue BasicObject, Exception
+                    # timeouts can't be rescued
+                end
+            "#).unwrap_err();
+
+            assert_eq!(Some(Abort::Timeout), err.abort());
+            assert_eq!("execution timed out", format!("{:?}", err));
+        });
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // the deadline is reset each time we enter the interpreter
+        mrb.try_context(|mrb| {
+            mrb.load_string("1 + 1")?;
+            Ok(())
+        }).expect("try_context");
+    }
+
+    #[test]
+    fn test_huge_timeout() {
+        let limits = Limits::new(None, Some(Duration::from_secs(u64::MAX)));
+        limits.reset();
+        assert_eq!(None, limits.check());
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        if !supported() {
+            return;
+        }
+
+        let mut mrb = Mrb::builder()
+            .instruction_limit(10_000)
+            .open()
+            .unwrap();
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string("while true; end").unwrap_err();
+            assert_eq!(Some(Abort::InstructionLimit), err.abort());
+        });
+
+        mrb.context(|mrb| {
+            let err = mrb.load_string("raise 'hello'").unwrap_err();
+            assert_eq!(None, err.abort());
+        });
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut mrb = Mrb::open();
+
+        let handle = match mrb.interrupt_handle() {
+            Ok(handle) => handle,
+            Err(err) => {
+                assert!(!supported());
+                assert_eq!(Error::Unsupported("interrupts require mruby built with MRB_ENABLE_DEBUG_HOOK"), err);
+                return;
+            }
+        };
+
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+
+        let result = mrb.try_context(|mrb| {
+            mrb.load_string(r#"
+                begin
+                    loop {}
+                ensure
+                    # interrupts keep firing until we've unwound completely
+                    loop {}
+                end
+            "#)?;
+
+            Ok(())
+        });
+
+        interrupter.join().unwrap();
+        assert_eq!(Err(Error::Aborted(Abort::Interrupted)), result);
+
+        // the interrupt has been delivered, so we can run scripts again
+        mrb.try_context(|mrb| {
+            mrb.load_string("1 + 1")?;
+            Ok(())
+        }).expect("try_context");
+    }
+}
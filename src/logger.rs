@@ -0,0 +1,117 @@
+use log::{Level, Record};
+
+use crate::{Context, MrbResult};
+use crate::object::MrbValue;
+
+// records from scripts are logged under this target, so they can be
+// filtered separately from the host application's own logging
+const TARGET: &str = "mruby";
+
+const LEVELS: &[(&str, Level)] = &[
+    ("debug", Level::Debug),
+    ("info", Level::Info),
+    ("warn", Level::Warn),
+    ("error", Level::Error),
+];
+
+fn log<'mrb>(ctx: &Context<'mrb>, level: Level) -> MrbResult<'mrb, MrbValue<'mrb>> {
+    if level > log::max_level() {
+        return Ok(ctx.nil_value());
+    }
+
+    // strings are logged as is, anything else is inspected
+    let message = ctx.arguments().iter()
+        .map(|arg| ctx.as_string(*arg).unwrap_or_else(|| ctx.inspect(*arg).into_owned()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let location = ctx.caller_location();
+
+    log::logger().log(&Record::builder()
+        .args(format_args!("{}", message))
+        .level(level)
+        .target(TARGET)
+        .file(location.as_ref().map(|(filename, _)| filename.as_str()))
+        .line(location.as_ref().map(|(_, line)| *line))
+        .build());
+
+    Ok(ctx.nil_value())
+}
+
+impl<'mrb> Context<'mrb> {
+    /// Defines a `Logger` module whose `debug`, `info`, `warn` and `error`
+    /// methods log through the `log` crate, with the calling script's
+    /// filename and line attached to each record. Use `tracing-log` to
+    /// forward these records to `tracing`.
+    pub fn define_logger(&self) -> MrbResult<'mrb, ()> {
+        self.load_string("module Logger; extend self; end")?;
+
+        for (name, level) in LEVELS {
+            let logger = self.class_get("Logger")?.expect("Logger");
+            let level = *level;
+
+            self.define_method(logger, name, move |ctx, _self| log(ctx, level))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use crate::Mrb;
+
+    type Entry = (Level, String, Option<String>, Option<u32>);
+
+    struct TestLogger(Mutex<Vec<Entry>>);
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            if record.target() == "mruby" {
+                self.0.lock().unwrap().push((
+                    record.level(),
+                    record.args().to_string(),
+                    record.file().map(str::to_owned),
+                    record.line(),
+                ));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+
+    #[test]
+    fn test_logger() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Info);
+
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            mrb.define_logger().unwrap();
+
+            mrb.load_string_with_filename(r#"
+                Logger.debug "too verbose"
+                Logger.info "hello", 42
+                Logger.error :oops
+            "#, "script.rb", 1).unwrap();
+        });
+
+        let records = LOGGER.0.lock().unwrap();
+
+        assert_eq!(*records, vec![
+            (Level::Info, "hello 42".to_owned(), Some("script.rb".to_owned()), Some(3)),
+            (Level::Error, ":oops".to_owned(), Some("script.rb".to_owned()), Some(4)),
+        ]);
+    }
+}
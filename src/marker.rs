@@ -2,7 +2,7 @@ use std::cell::Cell;
 use std::marker::PhantomData;
 use std::panic::{UnwindSafe, RefUnwindSafe};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Invariant<'a>(PhantomData<Cell<&'a ()>>);
 
 impl<'a> Invariant<'a> {
@@ -4,12 +4,13 @@ use std::os::raw::c_void;
 
 use crate::{MrbResult, Context};
 use crate::boundary;
+use crate::limits;
 use crate::object::{MrbValue, MrbClass};
 
 type BoxedFunc = Box<dyn for<'sub> Fn(&Context<'sub>, MrbValue<'sub>) -> MrbResult<'sub, MrbValue<'sub>> + 'static>;
 
 unsafe fn exc_panic_carrier(mrb: *mut mrb_sys::mrb_state) {
-    let ud = (*mrb).ud as *const mrb_sys::mrbrs_ud;
+    let ud = mrb_sys::mrbrs_get_ud(mrb);
     let carrier = (*ud).panic_carrier;
     (*mrb).exc = carrier;
 }
@@ -38,6 +39,13 @@ unsafe extern "C" fn mrbrs_method_dispatch_boxed_func(
     data: *mut c_void,
     retn: &mut mrb_sys::mrb_value,
 ) {
+    // calls into Rust are a safe point for enforcing execution limits on top
+    // of the code fetch hook, which only sees time spent running bytecode
+    if let Some(carrier) = limits::check(mrb) {
+        (*mrb).exc = carrier;
+        return;
+    }
+
     let ctx = Context::new(mrb);
 
     let result = boundary::into_rust(mrb, || {
@@ -0,0 +1,330 @@
+use std::convert::TryInto;
+use std::os::raw::c_void;
+
+use rmp::Marker;
+use rmp::encode;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult, MAX_DEPTH};
+use crate::object::{MrbValue, MrbException};
+
+/// The MessagePack ext type Symbols are encoded as, with the symbol's name
+/// as data.
+pub const MSGPACK_SYMBOL_EXT: i8 = 0;
+
+impl<'mrb> Context<'mrb> {
+    /// Encodes `value` as MessagePack. Strings holding valid UTF-8 are
+    /// encoded as str and other Strings as bin, while Symbols use the
+    /// `MSGPACK_SYMBOL_EXT` ext type. Hash keys may be any supported value.
+    /// Other values raise `TypeError`, and cyclic or too deeply nested
+    /// structures raise `ArgumentError`.
+    pub fn to_msgpack(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, Vec<u8>> {
+        let mut writer = Writer { ctx: self, out: Vec::new(), parents: Vec::new() };
+        writer.write_value(value)?;
+        Ok(writer.out)
+    }
+
+    /// Decodes a MessagePack value. str and bin both become Strings, and
+    /// `MSGPACK_SYMBOL_EXT` becomes a Symbol. Integers outside the Fixnum
+    /// range raise `RangeError`, and other ext types or malformed input
+    /// raise `ArgumentError`.
+    pub fn from_msgpack(&self, bytes: &[u8]) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let mut reader = Reader { ctx: self, input: bytes, depth: 0 };
+        let value = reader.read_value()?;
+
+        match reader.input {
+            [] => Ok(value),
+            _ => Err(reader.error("trailing data after value")),
+        }
+    }
+}
+
+struct Writer<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    out: Vec<u8>,
+
+    // the Arrays and Hashes enclosing the value being written
+    parents: Vec<*mut c_void>,
+}
+
+impl<'a, 'mrb> Writer<'a, 'mrb> {
+    fn len(&self, len: usize) -> MrbResult<'mrb, u32> {
+        len.try_into().map_err(|_| {
+            self.ctx.core_exception("ArgumentError", &format!("length {} is too long for MessagePack", len))
+        })
+    }
+
+    fn write_value(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        let ctx = self.ctx;
+        let raw = value.as_raw();
+
+        // writes to a Vec can't fail
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_FALSE if value.is_nil() => encode::write_nil(&mut self.out).unwrap(),
+            sys::mrb_vtype_MRB_TT_FALSE => encode::write_bool(&mut self.out, false).unwrap(),
+            sys::mrb_vtype_MRB_TT_TRUE => encode::write_bool(&mut self.out, true).unwrap(),
+            sys::mrb_vtype_MRB_TT_FIXNUM => { encode::write_sint(&mut self.out, value.as_i64().expect("Fixnum")).unwrap(); }
+            sys::mrb_vtype_MRB_TT_FLOAT => encode::write_f64(&mut self.out, value.as_f64().expect("Float")).unwrap(),
+            sys::mrb_vtype_MRB_TT_SYMBOL => {
+                let name = ctx.symbol_bytes(value.as_symbol().expect("Symbol"));
+                let len = self.len(name.len())?;
+                encode::write_ext_meta(&mut self.out, len, MSGPACK_SYMBOL_EXT).unwrap();
+                self.out.extend_from_slice(&name);
+            }
+            sys::mrb_vtype_MRB_TT_STRING => {
+                let bytes = ctx.string_bytes(value).expect("String");
+                let len = self.len(bytes.len())?;
+
+                match std::str::from_utf8(&bytes) {
+                    Ok(_) => { encode::write_str_len(&mut self.out, len).unwrap(); }
+                    Err(_) => { encode::write_bin_len(&mut self.out, len).unwrap(); }
+                }
+
+                self.out.extend_from_slice(&bytes);
+            }
+            sys::mrb_vtype_MRB_TT_ARRAY | sys::mrb_vtype_MRB_TT_HASH => {
+                let ptr = value.as_ptr();
+
+                if self.parents.contains(&ptr) {
+                    return Err(ctx.core_exception("ArgumentError", "circular reference detected in MessagePack conversion"));
+                }
+
+                if self.parents.len() == MAX_DEPTH {
+                    return Err(ctx.core_exception("ArgumentError", &format!("nesting of {} is too deep for MessagePack", MAX_DEPTH + 1)));
+                }
+
+                self.parents.push(ptr);
+                let result = self.write_container(value);
+                self.parents.pop();
+                return result;
+            }
+            _ => return Err(ctx.core_exception("TypeError", &format!("{} can't be converted to MessagePack", ctx.class_name(value)))),
+        }
+
+        Ok(())
+    }
+
+    fn write_container(&mut self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        if let Some(values) = self.ctx.array_entries(value) {
+            let len = self.len(values.len())?;
+            encode::write_array_len(&mut self.out, len).unwrap();
+
+            for value in values {
+                self.write_value(value)?;
+            }
+
+            return Ok(());
+        }
+
+        let entries = self.ctx.hash_entries(value)?;
+        let len = self.len(entries.len())?;
+        encode::write_map_len(&mut self.out, len).unwrap();
+
+        for (key, value) in entries {
+            self.write_value(key)?;
+            self.write_value(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a, 'mrb, 'b> {
+    ctx: &'a Context<'mrb>,
+    input: &'b [u8],
+    depth: usize,
+}
+
+impl<'a, 'mrb, 'b> Reader<'a, 'mrb, 'b> {
+    fn error(&self, message: &str) -> MrbException<'mrb> {
+        self.ctx.core_exception("ArgumentError", &format!("invalid MessagePack: {}", message))
+    }
+
+    fn take(&mut self, len: usize) -> MrbResult<'mrb, &'b [u8]> {
+        if len > self.input.len() {
+            return Err(self.error("unexpected end of input"));
+        }
+
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    // reads a big endian unsigned integer of `N` bytes
+    fn uint<const N: usize>(&mut self) -> MrbResult<'mrb, u64> {
+        let bytes = self.take(N)?;
+        Ok(bytes.iter().fold(0, |acc, byte| acc << 8 | u64::from(*byte)))
+    }
+
+    // reads the elements of an array or map one level deeper. as in the
+    // writer, other values don't count towards the limit
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> MrbResult<'mrb, T>) -> MrbResult<'mrb, T> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("nesting of {} is too deep", MAX_DEPTH + 1)));
+        }
+
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn read_value(&mut self) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let ctx = self.ctx;
+        let marker = Marker::from_u8(self.take(1)?[0]);
+
+        match marker {
+            Marker::Null => Ok(ctx.nil_value()),
+            Marker::True => Ok(ctx.bool_value(true)),
+            Marker::False => Ok(ctx.bool_value(false)),
+            Marker::FixPos(value) => Ok(ctx.fixnum_value(value.into())),
+            Marker::FixNeg(value) => Ok(ctx.fixnum_value(value.into())),
+            Marker::U8 => ctx.new_int(self.uint::<1>()?),
+            Marker::U16 => ctx.new_int(self.uint::<2>()?),
+            Marker::U32 => ctx.new_int(self.uint::<4>()?),
+            Marker::U64 => ctx.new_int(self.uint::<8>()?),
+            Marker::I8 => Ok(ctx.fixnum_value((self.uint::<1>()? as u8 as i8).into())),
+            Marker::I16 => Ok(ctx.fixnum_value((self.uint::<2>()? as u16 as i16).into())),
+            Marker::I32 => Ok(ctx.fixnum_value((self.uint::<4>()? as u32 as i32).into())),
+            Marker::I64 => ctx.new_int(self.uint::<8>()? as i64),
+            Marker::F32 => ctx.new_float(f32::from_bits(self.uint::<4>()? as u32).into()),
+            Marker::F64 => ctx.new_float(f64::from_bits(self.uint::<8>()?)),
+            Marker::FixStr(len) => self.read_string(len.into()),
+            Marker::Str8 | Marker::Bin8 => { let len = self.uint::<1>()?; self.read_string(len) }
+            Marker::Str16 | Marker::Bin16 => { let len = self.uint::<2>()?; self.read_string(len) }
+            Marker::Str32 | Marker::Bin32 => { let len = self.uint::<4>()?; self.read_string(len) }
+            Marker::FixArray(len) => self.read_array(len.into()),
+            Marker::Array16 => { let len = self.uint::<2>()?; self.read_array(len) }
+            Marker::Array32 => { let len = self.uint::<4>()?; self.read_array(len) }
+            Marker::FixMap(len) => self.read_map(len.into()),
+            Marker::Map16 => { let len = self.uint::<2>()?; self.read_map(len) }
+            Marker::Map32 => { let len = self.uint::<4>()?; self.read_map(len) }
+            Marker::FixExt1 => self.read_ext(1),
+            Marker::FixExt2 => self.read_ext(2),
+            Marker::FixExt4 => self.read_ext(4),
+            Marker::FixExt8 => self.read_ext(8),
+            Marker::FixExt16 => self.read_ext(16),
+            Marker::Ext8 => { let len = self.uint::<1>()?; self.read_ext(len) }
+            Marker::Ext16 => { let len = self.uint::<2>()?; self.read_ext(len) }
+            Marker::Ext32 => { let len = self.uint::<4>()?; self.read_ext(len) }
+            Marker::Reserved => Err(self.error("reserved marker 0xc1")),
+        }
+    }
+
+    fn read_string(&mut self, len: u64) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let bytes = self.take(len as usize)?;
+        self.ctx.new_string_bytes(bytes)
+    }
+
+    fn read_array(&mut self, len: u64) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let array = self.ctx.new_array()?;
+
+        self.nested(|reader| {
+            for _ in 0..len {
+                let value = reader.read_value()?;
+                reader.ctx.array_push(array, value)?;
+            }
+
+            Ok(array)
+        })
+    }
+
+    fn read_map(&mut self, len: u64) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let hash = self.ctx.new_hash()?;
+
+        self.nested(|reader| {
+            for _ in 0..len {
+                let key = reader.read_value()?;
+                let value = reader.read_value()?;
+                reader.ctx.hash_set(hash, key, value)?;
+            }
+
+            Ok(hash)
+        })
+    }
+
+    fn read_ext(&mut self, len: u64) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let ty = self.take(1)?[0] as i8;
+        let data = self.take(len as usize)?;
+
+        match ty {
+            MSGPACK_SYMBOL_EXT => self.ctx.intern_bytes(data),
+            ty => Err(self.error(&format!("unsupported ext type {}", ty))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Context, MAX_DEPTH};
+
+    fn to_msgpack(mrb: &Context, code: &str) -> Result<Vec<u8>, String> {
+        let value = mrb.load_string(code).unwrap();
+        mrb.to_msgpack(value).map_err(|err| format!("{:?}", err))
+    }
+
+    fn from_msgpack(mrb: &Context, bytes: &[u8]) -> Result<String, String> {
+        mrb.from_msgpack(bytes)
+            .map(|value| mrb.inspect(value).into_owned())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_msgpack() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            assert_eq!(b"\x93\xc0\xc3\xcd\x01\x00".to_vec(), to_msgpack(mrb, "[nil, true, 256]").unwrap());
+            assert_eq!(b"\x83\xd4\x00a\xa1b\xa1c\xc4\x01\xff\xcb\x3f\xf8\x00\x00\x00\x00\x00\x00\xff".to_vec(),
+                to_msgpack(mrb, r#"{ :a => "b", "c" => "\xff", 1.5 => -1 }"#).unwrap());
+
+            let code = r#"{ id: 42, args: ["text", "\x00\xff", :sym, [-1.25, false, nil]], "nested" => { 1 => 2 ** 40 } }"#;
+            let bytes = to_msgpack(mrb, code).unwrap();
+            assert_eq!(mrb.inspect(mrb.load_string(code).unwrap()), from_msgpack(mrb, &bytes).unwrap());
+
+            assert_eq!("circular reference detected in MessagePack conversion (ArgumentError)", to_msgpack(mrb, "a = [1]; a << a; a").unwrap_err());
+            assert_eq!("Range can't be converted to MessagePack (TypeError)", to_msgpack(mrb, "[1..2]").unwrap_err());
+            assert_eq!("[[1], [1]]", from_msgpack(mrb, &to_msgpack(mrb, "a = [1]; [a, a]").unwrap()).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_msgpack_nesting() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            // scalars don't count towards the limit, only the arrays and maps
+            // holding them
+            let nested = |depth| format!("a = {{ k: 'v' }}; {}.times {{ a = [a, 'leaf', :sym, 1.5] }}; a", depth - 1);
+
+            let code = nested(MAX_DEPTH);
+            let bytes = to_msgpack(mrb, &code).unwrap();
+            assert_eq!(mrb.inspect(mrb.load_string(&code).unwrap()), from_msgpack(mrb, &bytes).unwrap());
+
+            assert_eq!("nesting of 129 is too deep for MessagePack (ArgumentError)", to_msgpack(mrb, &nested(MAX_DEPTH + 1)).unwrap_err());
+
+            let mut bytes = vec![0x91; MAX_DEPTH];
+            bytes.push(0x90);
+            assert_eq!("invalid MessagePack: nesting of 129 is too deep (ArgumentError)", from_msgpack(mrb, &bytes).unwrap_err());
+        });
+    }
+
+    #[test]
+    fn test_from_msgpack() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            // integers of every width, and a float32
+            assert_eq!("[-32, 255, -129, 65535, -2147483648, 4294967296, 1.5]",
+                from_msgpack(mrb, b"\x97\xe0\xcc\xff\xd1\xff\x7f\xcd\xff\xff\xd2\x80\x00\x00\x00\xcf\x00\x00\x00\x01\x00\x00\x00\x00\xca\x3f\xc0\x00\x00").unwrap());
+
+            assert_eq!("integer 18446744073709551615 out of Fixnum range (RangeError)",
+                from_msgpack(mrb, b"\xcf\xff\xff\xff\xff\xff\xff\xff\xff").unwrap_err());
+
+            assert_eq!("invalid MessagePack: unexpected end of input (ArgumentError)", from_msgpack(mrb, b"\x92\x01").unwrap_err());
+            assert_eq!("invalid MessagePack: unsupported ext type -1 (ArgumentError)", from_msgpack(mrb, b"\xd6\xff\x00\x00\x00\x00").unwrap_err());
+            assert_eq!("invalid MessagePack: trailing data after value (ArgumentError)", from_msgpack(mrb, b"\x01\x02").unwrap_err());
+        });
+    }
+}
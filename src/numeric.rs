@@ -0,0 +1,76 @@
+use std::convert::TryInto;
+use std::fmt::Display;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult};
+use crate::object::MrbValue;
+
+/// Whether `int` fits in a Fixnum. This is the whole `i64` range unless
+/// mruby packs values into a word, which takes a bit for the type tag.
+pub(crate) fn fixable(int: i64) -> bool {
+    let (mut min, mut max): (sys::mrb_int, sys::mrb_int) = (0, 0);
+    unsafe { sys::mrbrs_fixnum_limits(&mut min as *mut _, &mut max as *mut _) };
+    min <= int && int <= max
+}
+
+impl<'mrb> Context<'mrb> {
+    /// Creates an Integer from any Rust integer. Values outside the Fixnum
+    /// range raise `RangeError`.
+    pub fn new_int<T>(&self, value: T) -> MrbResult<'mrb, MrbValue<'mrb>>
+        where T: TryInto<i64> + Display + Copy
+    {
+        match value.try_into() {
+            Ok(int) if fixable(int) => Ok(self.fixnum_value(int)),
+            _ => Err(self.core_exception("RangeError", &format!("integer {} out of Fixnum range", value))),
+        }
+    }
+
+    /// Creates a Float, which allocates when mruby packs values into a word.
+    pub fn new_float(&self, value: f64) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_float_value(self.mrb, value)
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Mrb;
+
+    #[test]
+    fn test_numeric() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let int = mrb.new_int(42u8).unwrap();
+            assert_eq!("42", mrb.inspect(int));
+            assert_eq!(Some(42), int.as_i64());
+            assert_eq!(Some(42.0), int.as_f64());
+
+            let int = mrb.new_int(-7i128).unwrap();
+            assert_eq!(Some(-7), int.as_i64());
+
+            let float = mrb.new_float(1.5).unwrap();
+            assert_eq!("1.5", mrb.inspect(float));
+            assert_eq!(Some(1.5), float.as_f64());
+            assert_eq!(None, float.as_i64());
+
+            let nan = mrb.new_float(f64::NAN).unwrap();
+            assert!(nan.as_f64().unwrap().is_nan());
+
+            assert_eq!(None, mrb.nil_value().as_i64());
+            assert_eq!(None, mrb.new_string("1").unwrap().as_f64());
+
+            let value = mrb.load_string("2 ** 40 + 1").unwrap();
+            assert_eq!(Some((1 << 40) + 1), value.as_i64());
+
+            let err = mrb.new_int(u64::MAX).unwrap_err();
+            assert_eq!("integer 18446744073709551615 out of Fixnum range (RangeError)", format!("{:?}", err));
+            let err = mrb.new_int(i128::MIN).unwrap_err();
+            assert_eq!(format!("integer {} out of Fixnum range (RangeError)", i128::MIN), format!("{:?}", err));
+        });
+    }
+}
@@ -1,7 +1,9 @@
 use std::borrow::Cow;
 use std::convert::TryInto;
 use std::fmt::{self, Debug};
+use std::os::raw::c_void;
 use std::slice;
+use std::str;
 
 use crate::marker::Invariant;
 
@@ -23,11 +25,116 @@ impl<'mrb> MrbValue<'mrb> {
     pub(crate) fn as_raw(self) -> mrb_sys::mrb_value {
         self.value
     }
+
+    /// The contents of a String, or None for other values. See
+    /// `Context::string_bytes` for a safe copy.
+    ///
+    /// # Safety
+    ///
+    /// The slice points into the String's own buffer. It must not be used
+    /// after anything that could modify or reallocate the String, which
+    /// includes running any Ruby code and the `str_*` functions on
+    /// `Context`.
+    pub unsafe fn as_bytes(self) -> Option<&'mrb [u8]> {
+        unsafe {
+            let mut len: mrb_sys::size_t = 0;
+            let ptr = mrb_sys::mrbrs_str_ptr(self.value, &mut len as *mut _);
+
+            if ptr.is_null() {
+                return None;
+            }
+
+            Some(slice::from_raw_parts(ptr as *const u8, len.try_into().unwrap()))
+        }
+    }
+
+    /// Like `as_bytes`, but also returns None for Strings which aren't valid
+    /// UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// As for `as_bytes`.
+    pub unsafe fn as_str(self) -> Option<&'mrb str> {
+        self.as_bytes().and_then(|bytes| str::from_utf8(bytes).ok())
+    }
+
+    /// The value of an Integer, or None for other values.
+    pub fn as_i64(self) -> Option<i64> {
+        let mut int: mrb_sys::mrb_int = 0;
+
+        match unsafe { mrb_sys::mrbrs_fixnum_get(self.value, &mut int as *mut _) } {
+            true => Some(int),
+            false => None,
+        }
+    }
+
+    /// The value of a Float or Integer, or None for other values. Integers
+    /// beyond 2**53 lose precision.
+    pub fn as_f64(self) -> Option<f64> {
+        let mut float: mrb_sys::mrb_float = 0.0;
+
+        match unsafe { mrb_sys::mrbrs_float_get(self.value, &mut float as *mut _) } {
+            true => Some(float),
+            false => self.as_i64().map(|int| int as f64),
+        }
+    }
+
+    /// The Symbol this value holds, or None for other values.
+    pub fn as_symbol(self) -> Option<MrbSymbol<'mrb>> {
+        let mut sym: mrb_sys::mrb_sym = 0;
+
+        match unsafe { mrb_sys::mrbrs_symbol_get(self.value, &mut sym as *mut _) } {
+            true => Some(unsafe { MrbSymbol::new(sym) }),
+            false => None,
+        }
+    }
+
+    pub(crate) fn is_nil(self) -> bool {
+        unsafe { mrb_sys::mrbrs_nil_p(self.value) }
+    }
+
+    // the object this value refers to. meaningless for immediate values such
+    // as Fixnums and Symbols
+    pub(crate) fn as_ptr(self) -> *mut c_void {
+        unsafe { mrb_sys::mrbrs_ptr(self.value) }
+    }
 }
 
 impl<'mrb> Debug for MrbValue<'mrb> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "MrbValue({:x?})", unsafe { self.value.value.p })
+        write!(f, "MrbValue({:x?})", self.as_ptr())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MrbSymbol<'mrb> {
+    sym: mrb_sys::mrb_sym,
+    _inv: Invariant<'mrb>,
+}
+
+impl<'mrb> MrbSymbol<'mrb> {
+    /// Safety: you guarantee that `sym` was interned in the `'mrb` state
+    pub(crate) unsafe fn new(sym: mrb_sys::mrb_sym) -> Self {
+        MrbSymbol {
+            sym,
+            _inv: Invariant::phantom(),
+        }
+    }
+
+    pub(crate) fn as_raw(self) -> mrb_sys::mrb_sym {
+        self.sym
+    }
+}
+
+impl<'mrb> From<MrbSymbol<'mrb>> for MrbValue<'mrb> {
+    fn from(sym: MrbSymbol<'mrb>) -> Self {
+        unsafe { MrbValue::new(mrb_sys::mrbrs_symbol_value(sym.sym)) }
+    }
+}
+
+impl<'mrb> Debug for MrbSymbol<'mrb> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MrbSymbol({})", self.sym)
     }
 }
 
@@ -51,6 +158,10 @@ impl<'mrb, T> MrbPtr<'mrb, T> {
         self.ptr
     }
 
+    pub(crate) fn mrb(&self) -> *mut mrb_sys::mrb_state {
+        self.mrb
+    }
+
     pub(crate) unsafe fn cast<U>(self) -> MrbPtr<'mrb, U> {
         MrbPtr {
             mrb: self.mrb,
@@ -99,6 +210,24 @@ impl<'mrb> Into<MrbObject<'mrb>> for MrbException<'mrb> {
     }
 }
 
+impl<'mrb> MrbException<'mrb> {
+    /// The backtrace recorded when the exception was raised, innermost frame
+    /// first. Empty for exceptions which were never raised.
+    pub fn backtrace(&self) -> Vec<String> {
+        unsafe {
+            let mut len: mrb_sys::size_t = 0;
+            let ptr = mrb_sys::mrbrs_exc_backtrace(self.0.mrb(), self.0.as_ptr(), &mut len as *mut _);
+
+            if ptr.is_null() {
+                return Vec::new();
+            }
+
+            let bytes = slice::from_raw_parts(ptr as *const u8, len.try_into().unwrap());
+            String::from_utf8_lossy(bytes).lines().map(str::to_owned).collect()
+        }
+    }
+}
+
 impl<'mrb> Debug for MrbException<'mrb> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0.inspect())
@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::{Mrb, Context, MrbResult};
+use crate::object::MrbValue;
+
+pub(crate) struct Output {
+    stdout: RefCell<Box<dyn Write>>,
+    stderr: RefCell<Box<dyn Write>>,
+}
+
+impl Output {
+    fn write<'mrb>(ctx: &Context<'mrb>, writer: &RefCell<Box<dyn Write>>, bytes: &[u8]) -> MrbResult<'mrb, ()> {
+        let mut writer = writer.borrow_mut();
+
+        writer.write_all(bytes)
+            .and_then(|()| writer.flush())
+            .map_err(|err| ctx.core_exception("RuntimeError", &format!("write failed: {}", err)))
+    }
+}
+
+fn to_bytes<'mrb>(ctx: &Context<'mrb>, value: MrbValue<'mrb>) -> MrbResult<'mrb, Vec<u8>> {
+    let string = ctx.to_s(value)?;
+    Ok(ctx.string_bytes(string).unwrap_or_default())
+}
+
+// formats arguments the way puts does, flattening arrays and printing
+// recursive ones as [...]. an empty array prints an empty line
+fn lines<'mrb>(ctx: &Context<'mrb>, args: &[MrbValue<'mrb>], seen: &mut Vec<MrbValue<'mrb>>, out: &mut Vec<u8>) -> MrbResult<'mrb, ()> {
+    if args.is_empty() {
+        out.push(b'\n');
+    }
+
+    for arg in args {
+        let line = match ctx.array_entries(*arg) {
+            Some(_) if seen.iter().any(|array| same_object(*array, *arg)) => b"[...]".to_vec(),
+            Some(entries) => {
+                seen.push(*arg);
+                lines(ctx, &entries, seen, out)?;
+                seen.pop();
+                continue;
+            }
+            None => to_bytes(ctx, *arg)?,
+        };
+
+        out.extend_from_slice(&line);
+
+        if !line.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+    }
+
+    Ok(())
+}
+
+fn same_object(a: MrbValue, b: MrbValue) -> bool {
+    a.as_ptr() == b.as_ptr()
+}
+
+impl Mrb {
+    /// Sends output from `print`, `puts` and `p` to `writer` instead of the
+    /// process's stdout.
+    pub fn set_stdout(&mut self, writer: impl Write + 'static) {
+        *self.output().stdout.borrow_mut() = Box::new(writer);
+    }
+
+    /// Sends output from `warn` to `writer` instead of the process's stderr.
+    pub fn set_stderr(&mut self, writer: impl Write + 'static) {
+        *self.output().stderr.borrow_mut() = Box::new(writer);
+    }
+
+    // defines Kernel output methods in the manner of mruby-print, leaving
+    // out any the sandbox policy denies
+    fn output(&mut self) -> Rc<Output> {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+
+        let output = Rc::new(Output {
+            stdout: RefCell::new(Box::new(io::stdout())),
+            stderr: RefCell::new(Box::new(io::stderr())),
+        });
+
+        let denied = |name: &str| {
+            self.sandbox.as_ref()
+                .map(|policy| policy.denies(&format!("Kernel#{}", name)))
+                .unwrap_or(false)
+        };
+
+        let (print, puts, p, warn) = (!denied("print"), !denied("puts"), !denied("p"), !denied("warn"));
+
+        let out = output.clone();
+
+        self.try_context(|ctx| {
+            let kernel = || ctx.class_get("Kernel").map(|kernel| kernel.expect("Kernel"));
+
+            if print {
+                let output = out.clone();
+                ctx.define_method(kernel()?, "print", move |ctx, _self| {
+                    for arg in ctx.arguments() {
+                        Output::write(ctx, &output.stdout, &to_bytes(ctx, *arg)?)?;
+                    }
+
+                    Ok(ctx.nil_value())
+                })?;
+            }
+
+            if puts {
+                let output = out.clone();
+                ctx.define_method(kernel()?, "puts", move |ctx, _self| {
+                    let mut bytes = Vec::new();
+                    lines(ctx, ctx.arguments(), &mut Vec::new(), &mut bytes)?;
+                    Output::write(ctx, &output.stdout, &bytes)?;
+                    Ok(ctx.nil_value())
+                })?;
+            }
+
+            if p {
+                let output = out.clone();
+                ctx.define_method(kernel()?, "p", move |ctx, _self| {
+                    let args = ctx.arguments();
+
+                    for arg in args {
+                        let inspect = ctx.funcall(*arg, "inspect", &[])?;
+                        let mut bytes = to_bytes(ctx, inspect)?;
+                        bytes.push(b'\n');
+                        Output::write(ctx, &output.stdout, &bytes)?;
+                    }
+
+                    match args {
+                        [] => Ok(ctx.nil_value()),
+                        [arg] => Ok(*arg),
+                        _ => {
+                            let array = ctx.new_array()?;
+
+                            for arg in args {
+                                ctx.array_push(array, *arg)?;
+                            }
+
+                            Ok(array)
+                        }
+                    }
+                })?;
+            }
+
+            if warn {
+                let output = out.clone();
+                ctx.define_method(kernel()?, "warn", move |ctx, _self| {
+                    let args = ctx.arguments();
+
+                    // unlike puts, warn with no arguments prints nothing
+                    if !args.is_empty() {
+                        let mut bytes = Vec::new();
+                        lines(ctx, args, &mut Vec::new(), &mut bytes)?;
+                        Output::write(ctx, &output.stderr, &bytes)?;
+                    }
+
+                    Ok(ctx.nil_value())
+                })?;
+            }
+
+            Ok(())
+        }).expect("install output methods");
+
+        self.output = Some(output.clone());
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    use crate::{Mrb, SandboxPolicy};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_set_stdout() {
+        let stdout = Buffer::default();
+        let stderr = Buffer::default();
+
+        let mut mrb = Mrb::open();
+        mrb.set_stdout(stdout.clone());
+        mrb.set_stderr(stderr.clone());
+
+        mrb.context(|mrb| {
+            mrb.load_string(r#"
+                print "a", 1
+                puts
+                puts "b", ["c", ["d\n"]]
+                p :e, nil
+                warn "f"
+            "#).unwrap();
+        });
+
+        assert_eq!("a1\nb\nc\nd\n:e\nnil\n", stdout.contents());
+        assert_eq!("f\n", stderr.contents());
+
+        // writers can be swapped at any time
+        let other = Buffer::default();
+        mrb.set_stdout(other.clone());
+
+        mrb.context(|mrb| {
+            assert_eq!("2", mrb.inspect(mrb.load_string("p 1 + 1").unwrap()));
+        });
+
+        assert_eq!("2\n", other.contents());
+    }
+
+    #[test]
+    fn test_puts_arrays() {
+        let stdout = Buffer::default();
+
+        let mut mrb = Mrb::open();
+        mrb.set_stdout(stdout.clone());
+
+        mrb.context(|mrb| {
+            mrb.load_string(r#"
+                puts []
+                puts [[], 1]
+                a = [2]
+                a << a
+                puts a
+            "#).unwrap();
+        });
+
+        assert_eq!("\n\n1\n2\n[...]\n", stdout.contents());
+    }
+
+    #[test]
+    fn test_raw_bytes() {
+        let stdout = Buffer::default();
+
+        let mut mrb = Mrb::open();
+        mrb.set_stdout(stdout.clone());
+
+        mrb.context(|mrb| {
+            mrb.load_string(r#"print "\xff\xfe""#).unwrap();
+        });
+
+        assert_eq!(vec![0xff, 0xfe], *stdout.0.borrow());
+    }
+
+    #[test]
+    fn test_no_primitives() {
+        let mut mrb = Mrb::open();
+        mrb.set_stdout(Buffer::default());
+
+        mrb.context(|mrb| {
+            // output is written straight from Rust, so there's nothing
+            // internal for scripts to call or redefine
+            assert!(mrb.load_string("__mrbrs_stdout('x')").is_err());
+        });
+    }
+
+    #[test]
+    fn test_sandbox_denies_output() {
+        let policy = SandboxPolicy::default().deny("Kernel#puts");
+        let mut mrb = Mrb::builder().sandbox(policy).open().unwrap();
+        mrb.set_stdout(Buffer::default());
+
+        mrb.context(|mrb| {
+            assert!(mrb.load_string("puts 1").is_err());
+            assert!(mrb.load_string("print 1").is_ok());
+        });
+    }
+}
@@ -0,0 +1,105 @@
+use std::error;
+
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+
+use crate::{Mrb, Context, Error, MrbResult, Input};
+use crate::object::MrbException;
+
+type Init = Box<dyn for<'mrb> FnOnce(&Context<'mrb>) -> MrbResult<'mrb, ()>>;
+
+/// An interactive shell. Results are printed with `inspect` and uncaught
+/// exceptions with their full backtrace.
+pub struct Repl {
+    filename: String,
+    init: Vec<Init>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl {
+            filename: "(repl)".to_owned(),
+            init: Vec::new(),
+        }
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::default()
+    }
+
+    /// Sets the filename reported in syntax errors and backtraces.
+    pub fn filename(mut self, filename: &str) -> Self {
+        self.filename = filename.to_owned();
+        self
+    }
+
+    /// Runs `f` before the first prompt, so the embedding application can
+    /// define its classes and methods.
+    pub fn init(mut self, f: impl for<'mrb> FnOnce(&Context<'mrb>) -> MrbResult<'mrb, ()> + 'static) -> Self {
+        self.init.push(Box::new(f));
+        self
+    }
+
+    /// Reads and evaluates lines until end of input.
+    pub fn run(self, mrb: &mut Mrb) -> Result<(), Box<dyn error::Error>> {
+        for init in self.init {
+            mrb.try_context(init)?;
+        }
+
+        let mut session = mrb.session(&self.filename)?;
+        let mut editor = Editor::<()>::new();
+        let mut buffer = String::new();
+
+        loop {
+            let prompt = if buffer.is_empty() { "> " } else { "* " };
+
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                }
+                Err(ReadlineError::Interrupted) => {
+                    // ^C discards a partially entered expression
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+
+            match session.check(&buffer) {
+                Ok(Input::Incomplete) => continue,
+                Ok(Input::Complete) => {
+                    session.eval(&buffer, |ctx, result| match result {
+                        Ok(value) => println!(" => {}", ctx.inspect(value)),
+                        Err(exc) => report(exc),
+                    });
+                }
+                Ok(Input::Invalid(diagnostics)) => {
+                    for diagnostic in diagnostics {
+                        eprintln!("{}:{}", self.filename, diagnostic);
+                    }
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+
+            editor.add_history_entry(buffer.trim_end());
+            buffer.clear();
+        }
+    }
+}
+
+fn report(exc: MrbException) {
+    if exc.abort().is_some() {
+        eprintln!("{}", Error::from(exc));
+        return;
+    }
+
+    eprintln!("{:?}", exc);
+
+    for line in exc.backtrace() {
+        eprintln!("\tfrom {}", line);
+    }
+}
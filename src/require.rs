@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult};
+use crate::object::{MrbValue, MrbClass, MrbException};
+use crate::resolver::{Module, ModuleResolver, DirectoryResolver};
+
+struct Loader {
+    resolver: Box<dyn ModuleResolver>,
+    loaded: RefCell<HashSet<String>>,
+
+    // ids of the modules currently being required, outermost first
+    loading: RefCell<Vec<String>>,
+}
+
+impl Loader {
+    fn require<'mrb>(&self, ctx: &Context<'mrb>, name: &str, relative_to: Option<&str>) -> MrbResult<'mrb, bool> {
+        let id = match self.resolver.resolve(name, relative_to) {
+            Some(id) => id,
+            None => return Err(ctx.load_error(&format!("cannot load such file -- {}", name))),
+        };
+
+        if let Some(idx) = self.loading.borrow().iter().position(|loading| *loading == id) {
+            let cycle = self.loading.borrow()[idx..].iter()
+                .chain(Some(&id))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            return Err(ctx.load_error(&format!("cyclic require detected: {}", cycle.join(" -> "))));
+        }
+
+        if self.loaded.borrow().contains(&id) {
+            return Ok(false);
+        }
+
+        let module = self.resolver.load(&id)
+            .map_err(|err| ctx.load_error(&format!("{}: {}", id, err)))?;
+
+        // marked as loaded up front so that nothing the script does to
+        // $LOADED_FEATURES can get it run twice
+        self.loaded.borrow_mut().insert(id.clone());
+
+        // the borrow must not be held while loading, since the module may
+        // well require others
+        self.loading.borrow_mut().push(id.clone());
+        let result = ctx.load_module(&id, &module);
+        self.loading.borrow_mut().pop();
+
+        if let Err(exc) = result {
+            self.loaded.borrow_mut().remove(&id);
+            return Err(exc);
+        }
+
+        // scripts are free to replace $LOADED_FEATURES, so it only reflects
+        // what has been loaded as long as it's still an array
+        let _ = ctx.global_get("$LOADED_FEATURES")
+            .and_then(|features| ctx.array_push(features, ctx.new_string(&id)?));
+
+        Ok(true)
+    }
+}
+
+fn feature_name<'mrb>(ctx: &Context<'mrb>, method: &str) -> MrbResult<'mrb, String> {
+    match ctx.arguments() {
+        [name] => match ctx.as_string(*name) {
+            Some(name) => Ok(name),
+            None => Err(ctx.core_exception("TypeError", &format!("{} expects a String", method))),
+        },
+        args => Err(ctx.core_exception("ArgumentError", &format!("wrong number of arguments (given {}, expected 1)", args.len()))),
+    }
+}
+
+impl<'mrb> Context<'mrb> {
+    /// Runs the Ruby source or bytecode file at `path`. Errors reading the
+    /// file are raised as `LoadError`.
+    pub fn load_file(&self, path: impl AsRef<Path>) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let path = path.as_ref();
+
+        let source = fs::read(path).map_err(|err| {
+            self.load_error(&format!("{}: {}", path.display(), err))
+        })?;
+
+        self.load_module(&path.to_string_lossy(), &Module::from_bytes(source))
+    }
+
+    /// Runs Ruby source or bytecode, reporting `filename` in errors and
+    /// backtraces. Unlike `load_string` the source needn't be UTF-8.
+    pub fn load_module(&self, filename: &str, module: &Module) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let source = match module {
+            Module::Source(source) => source,
+            Module::Bytecode(bytecode) => return self.load_bytecode(bytecode),
+        };
+
+        let filename = CString::new(filename)
+            .map_err(|_| self.load_error(&format!("invalid filename: {}", filename)))?;
+
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_load_nstring_filename(
+                self.mrb,
+                source.as_ptr() as *const i8,
+                source.len().try_into().unwrap(),
+                filename.as_ptr(),
+                1,
+            )
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    /// Defines `Kernel#require` and `Kernel#require_relative`, which search
+    /// `load_paths` in order for `.rb` and `.mrb` files. Each file is only
+    /// loaded once, and is recorded in `$LOADED_FEATURES` once it has
+    /// loaded successfully.
+    pub fn enable_require<P: AsRef<Path>>(&self, load_paths: &[P]) -> MrbResult<'mrb, ()> {
+        self.enable_require_with(DirectoryResolver::new(load_paths))
+    }
+
+    /// Like `enable_require`, but finds modules with `resolver`.
+    pub fn enable_require_with(&self, resolver: impl ModuleResolver + 'static) -> MrbResult<'mrb, ()> {
+        // make sure LoadError exists up front, so scripts can rescue it
+        self.load_error_class()?;
+
+        let features = self.new_array()?;
+        self.global_set("$LOADED_FEATURES", features)?;
+        self.global_set("$\"", features)?;
+
+        let loader = Rc::new(Loader {
+            resolver: Box::new(resolver),
+            loaded: RefCell::new(HashSet::new()),
+            loading: RefCell::new(Vec::new()),
+        });
+
+        let kernel = self.class_get("Kernel")?.expect("Kernel");
+
+        let require = loader.clone();
+        self.define_method(kernel, "require", move |ctx, _self| {
+            let name = feature_name(ctx, "require")?;
+            let required = require.require(ctx, &name, None)?;
+            Ok(ctx.bool_value(required))
+        })?;
+
+        let kernel = self.class_get("Kernel")?.expect("Kernel");
+
+        self.define_method(kernel, "require_relative", move |ctx, _self| {
+            let name = feature_name(ctx, "require_relative")?;
+            let caller = ctx.caller_location().map(|(filename, _)| filename).unwrap_or_default();
+            let required = loader.require(ctx, &name, Some(&caller))?;
+            Ok(ctx.bool_value(required))
+        })?;
+
+        Ok(())
+    }
+
+    fn load_error_class(&self) -> MrbResult<'mrb, MrbClass<'mrb>> {
+        match self.class_get("LoadError")? {
+            Some(class) => Ok(class),
+            None => {
+                let script_error = self.class_get("ScriptError")?.expect("ScriptError");
+                self.define_class("LoadError", script_error)
+            }
+        }
+    }
+
+    fn load_error(&self, message: &str) -> MrbException<'mrb> {
+        match self.load_error_class() {
+            Ok(class) => self.new_exception(class, message),
+            Err(exc) => exc,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use crate::{Mrb, Context};
+
+    fn eval(mrb: &Context, code: &str) -> Result<String, String> {
+        mrb.load_string(code)
+            .map(|val| mrb.inspect(val).to_string())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    fn fixtures(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mrb-rs-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (path, code) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn test_load_file() {
+        let dir = fixtures("load_file", &[("script.rb", "[__FILE__, 1 + 2]")]);
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let val = mrb.load_file(dir.join("script.rb")).unwrap();
+            assert!(mrb.inspect(val).ends_with("script.rb\", 3]"));
+
+            let err = mrb.load_file(dir.join("missing.rb")).unwrap_err();
+            assert!(format!("{:?}", err).ends_with("(LoadError)"));
+        });
+
+        let bytecode = mrb.compile("'from bytecode'", "compiled.rb").unwrap();
+        fs::write(dir.join("compiled.mrb"), bytecode).unwrap();
+
+        mrb.context(|mrb| {
+            let val = mrb.load_file(dir.join("compiled.mrb")).unwrap();
+            assert_eq!("\"from bytecode\"", mrb.inspect(val));
+        });
+    }
+
+    #[test]
+    fn test_require() {
+        let dir = fixtures("require", &[
+            ("lib/greeting.rb", "require_relative 'greeting/name'\ndef greeting; \"hello #{NAME}\"; end"),
+            ("lib/greeting/name.rb", "$count = ($count || 0) + 1\nNAME = 'world'"),
+            ("lib/cycle_a.rb", "require 'cycle_b'"),
+            ("lib/cycle_b.rb", "require 'cycle_a'"),
+            ("lib/broken.rb", "raise 'broken'"),
+            ("lib/counter.rb", "$counter = ($counter || 0) + 1"),
+        ]);
+
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            mrb.enable_require(&[dir.join("lib")]).unwrap();
+
+            assert_eq!("true", eval(mrb, "require 'greeting'").unwrap());
+            assert_eq!("false", eval(mrb, "require 'greeting'").unwrap());
+            assert_eq!("false", eval(mrb, "require 'greeting/name'").unwrap());
+            assert_eq!("\"hello world\"", eval(mrb, "greeting").unwrap());
+            assert_eq!("1", eval(mrb, "$count").unwrap());
+            assert_eq!("2", eval(mrb, "$LOADED_FEATURES.size").unwrap());
+
+            let err = eval(mrb, "require 'cycle_a'").unwrap_err();
+            assert!(err.starts_with("cyclic require detected: "));
+            assert!(err.contains("cycle_a.rb -> "));
+            assert!(err.ends_with("cycle_a.rb (LoadError)"));
+
+            assert_eq!("cannot load such file -- missing (LoadError)", eval(mrb, "require 'missing'").unwrap_err());
+
+            // files which raise aren't marked as loaded
+            assert_eq!("broken (RuntimeError)", eval(mrb, "require 'broken'").unwrap_err());
+            assert_eq!("broken (RuntimeError)", eval(mrb, "require 'broken'").unwrap_err());
+
+            // loaded files are tracked even if $LOADED_FEATURES is clobbered
+            assert_eq!("nil", eval(mrb, "$LOADED_FEATURES = nil").unwrap());
+            assert_eq!("true", eval(mrb, "require 'counter'").unwrap());
+            assert_eq!("false", eval(mrb, "require 'counter'").unwrap());
+            assert_eq!("1", eval(mrb, "$counter").unwrap());
+        });
+    }
+}
@@ -0,0 +1,256 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// tried in order when a required name has no extension
+const EXTENSIONS: &[&str] = &["rb", "mrb"];
+
+/// The contents of a module found by a `ModuleResolver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Module {
+    Source(Vec<u8>),
+    Bytecode(Vec<u8>),
+}
+
+impl Module {
+    /// Treats `bytes` as bytecode if it starts with a RITE header, or as
+    /// Ruby source otherwise.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        if bytes.starts_with(b"RITE") {
+            Module::Bytecode(bytes)
+        } else {
+            Module::Source(bytes)
+        }
+    }
+}
+
+/// Finds the modules loaded by `require` and `require_relative`.
+///
+/// Modules are identified by an id, which is also used as the filename in
+/// backtraces. `require_relative` resolves names against the filename of
+/// the calling code, so bytecode should be compiled with its id as filename.
+pub trait ModuleResolver {
+    /// Maps a name passed to `require` to a module id. For `require_relative`
+    /// `relative_to` holds the id of the requiring module, which is empty if
+    /// the caller didn't come from a module.
+    fn resolve(&self, name: &str, relative_to: Option<&str>) -> Option<String>;
+
+    /// Fetches the module with id `id`, as returned by `resolve`.
+    fn load(&self, id: &str) -> io::Result<Module>;
+}
+
+/// Resolves modules from files on disk, searching a list of load paths.
+/// Names that are absolute or start with `./` or `../` are used as is.
+#[derive(Debug, Clone)]
+pub struct DirectoryResolver {
+    load_paths: Vec<PathBuf>,
+}
+
+impl DirectoryResolver {
+    pub fn new<P: AsRef<Path>>(load_paths: &[P]) -> Self {
+        DirectoryResolver {
+            load_paths: load_paths.iter().map(|path| path.as_ref().to_owned()).collect(),
+        }
+    }
+}
+
+impl ModuleResolver for DirectoryResolver {
+    fn resolve(&self, name: &str, relative_to: Option<&str>) -> Option<String> {
+        let path = Path::new(name);
+
+        let found = if let Some(base) = relative_to {
+            let dir = Path::new(base).parent().unwrap_or_else(|| Path::new(""));
+            find_file(&dir.join(path))
+        } else if path.is_absolute() || name.starts_with("./") || name.starts_with("../") {
+            find_file(path)
+        } else {
+            self.load_paths.iter()
+                .filter_map(|dir| find_file(&dir.join(path)))
+                .next()
+        };
+
+        // canonical paths make sure each file is only loaded once, however
+        // it was named
+        let path = found?.canonicalize().ok()?;
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    fn load(&self, id: &str) -> io::Result<Module> {
+        fs::read(id).map(Module::from_bytes)
+    }
+}
+
+fn find_file(path: &Path) -> Option<PathBuf> {
+    if path.extension().is_some() && path.is_file() {
+        return Some(path.to_owned());
+    }
+
+    EXTENSIONS.iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Resolves modules from an in-memory map of `/` separated paths to their
+/// contents, such as scripts loaded from a database.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    modules: HashMap<String, Cow<'static, [u8]>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        MemoryResolver::default()
+    }
+
+    /// Adds a module. Its contents may be source or bytecode.
+    pub fn insert(mut self, path: &str, contents: impl Into<Cow<'static, [u8]>>) -> Self {
+        self.modules.insert(normalize(path), contents.into());
+        self
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, name: &str, relative_to: Option<&str>) -> Option<String> {
+        resolve_path(name, relative_to, |path| self.modules.contains_key(path))
+    }
+
+    fn load(&self, id: &str) -> io::Result<Module> {
+        match self.modules.get(id) {
+            Some(contents) => Ok(Module::from_bytes(contents.to_vec())),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// Resolves modules from a directory embedded in the binary with
+/// `include_dir::include_dir!`.
+#[cfg(feature = "include_dir")]
+#[derive(Debug, Clone)]
+pub struct EmbeddedResolver {
+    dir: &'static include_dir::Dir<'static>,
+}
+
+#[cfg(feature = "include_dir")]
+impl EmbeddedResolver {
+    pub fn new(dir: &'static include_dir::Dir<'static>) -> Self {
+        EmbeddedResolver { dir }
+    }
+}
+
+#[cfg(feature = "include_dir")]
+impl ModuleResolver for EmbeddedResolver {
+    fn resolve(&self, name: &str, relative_to: Option<&str>) -> Option<String> {
+        resolve_path(name, relative_to, |path| self.dir.get_file(path).is_some())
+    }
+
+    fn load(&self, id: &str) -> io::Result<Module> {
+        match self.dir.get_file(id) {
+            Some(file) => Ok(Module::from_bytes(file.contents().to_vec())),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+fn resolve_path(name: &str, relative_to: Option<&str>, exists: impl Fn(&str) -> bool) -> Option<String> {
+    let path = match relative_to {
+        Some(base) => {
+            let dir = base.rfind('/').map(|idx| &base[..idx]).unwrap_or("");
+            normalize(&format!("{}/{}", dir, name))
+        }
+        None => normalize(name),
+    };
+
+    if exists(&path) {
+        return Some(path);
+    }
+
+    EXTENSIONS.iter()
+        .map(|ext| format!("{}.{}", path, ext))
+        .find(|path| exists(path))
+}
+
+// resolves `.` and `..` components, so each module has exactly one id
+fn normalize(path: &str) -> String {
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => { components.pop(); }
+            component => components.push(component),
+        }
+    }
+
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModuleResolver, MemoryResolver, Module};
+    use crate::Mrb;
+
+    #[test]
+    fn test_memory_resolver() {
+        let resolver = MemoryResolver::new()
+            .insert("app/main.rb", &b"require_relative 'util'"[..])
+            .insert("app/util.rb", &b"1"[..])
+            .insert("lib/json.rb", b"2".to_vec());
+
+        assert_eq!(Some("app/main.rb".to_owned()), resolver.resolve("app/main", None));
+        assert_eq!(Some("app/util.rb".to_owned()), resolver.resolve("util", Some("app/main.rb")));
+        assert_eq!(Some("lib/json.rb".to_owned()), resolver.resolve("../lib/json.rb", Some("app/main.rb")));
+        assert_eq!(None, resolver.resolve("missing", None));
+
+        assert_eq!(Module::Source(b"1".to_vec()), resolver.load("app/util.rb").unwrap());
+    }
+
+    #[test]
+    fn test_require_from_memory() {
+        let mut mrb = Mrb::open();
+        let bytecode = mrb.compile("def answer; 42; end", "lib/answer.rb").unwrap();
+
+        let resolver = MemoryResolver::new()
+            .insert("app/main.rb", &b"require 'lib/answer'\nrequire_relative 'helper'\nhelper(answer)"[..])
+            .insert("app/helper.rb", &b"def helper(n); n + 1; end"[..])
+            .insert("lib/answer.mrb", bytecode);
+
+        mrb.context(|mrb| {
+            mrb.enable_require_with(resolver).unwrap();
+
+            mrb.load_string("require 'app/main'").unwrap();
+            assert_eq!("43", mrb.inspect(mrb.load_string("helper(answer)").unwrap()));
+
+            let features = mrb.load_string("$LOADED_FEATURES").unwrap();
+            assert_eq!("[\"lib/answer.mrb\", \"app/helper.rb\", \"app/main.rb\"]", mrb.inspect(features));
+        });
+    }
+
+    #[cfg(feature = "include_dir")]
+    #[test]
+    fn test_require_embedded() {
+        use include_dir::{include_dir, Dir};
+        use super::EmbeddedResolver;
+
+        static FIXTURES: Dir = include_dir!("$CARGO_MANIFEST_DIR/fixtures/embedded");
+
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            mrb.enable_require_with(EmbeddedResolver::new(&FIXTURES)).unwrap();
+
+            mrb.load_string("require 'app/main'").unwrap();
+            assert_eq!("43", mrb.inspect(mrb.load_string("helper(answer)").unwrap()));
+
+            let features = mrb.load_string("$LOADED_FEATURES").unwrap();
+            assert_eq!("[\"lib/answer/value.rb\", \"lib/answer.rb\", \"app/helper.rb\", \"app/main.rb\"]", mrb.inspect(features));
+
+            let err = mrb.load_string("require 'lib/missing'").unwrap_err();
+            assert_eq!("cannot load such file -- lib/missing (LoadError)", format!("{:?}", err));
+
+            let err = mrb.load_string("require_relative 'missing'").unwrap_err();
+            assert_eq!("cannot load such file -- missing (LoadError)", format!("{:?}", err));
+        });
+    }
+}
@@ -0,0 +1,283 @@
+use std::ffi::CString;
+
+use crate::{Context, MrbResult};
+use crate::object::{MrbClass, MrbPtr};
+
+/// Describes which parts of the core library scripts may not use.
+///
+/// Rules name a top level class or module and optionally a method:
+/// `"Kernel#eval"` is an instance method, `"Kernel.exit"` is a singleton
+/// method and `"ObjectSpace"` is the constant itself. Rules naming classes or
+/// methods which don't exist in this build of mruby are ignored.
+#[derive(Debug, Clone)]
+pub struct SandboxPolicy {
+    deny: Vec<String>,
+    deny_string_eval: Vec<String>,
+    allow: Vec<String>,
+    protect_core_classes: bool,
+}
+
+impl SandboxPolicy {
+    /// A policy which denies nothing. Use this as a starting point for an
+    /// allowlist-style policy.
+    pub fn new() -> Self {
+        SandboxPolicy {
+            deny: Vec::new(),
+            deny_string_eval: Vec::new(),
+            allow: Vec::new(),
+            protect_core_classes: false,
+        }
+    }
+
+    /// Removes a method or constant.
+    pub fn deny(mut self, rule: &str) -> Self {
+        self.deny.push(rule.to_owned());
+        self
+    }
+
+    /// Restricts an eval-style method to its block form, raising
+    /// `SecurityError` if it is passed a string to evaluate.
+    pub fn deny_string_eval(mut self, rule: &str) -> Self {
+        self.deny_string_eval.push(rule.to_owned());
+        self
+    }
+
+    /// Exempts a method or constant from any rule denying it.
+    pub fn allow(mut self, rule: &str) -> Self {
+        self.allow.push(rule.to_owned());
+        self
+    }
+
+    /// Raises `SecurityError` when scripts call `define_method` on any class
+    /// or module that existed when the sandbox was applied.
+    ///
+    /// This does not stop scripts from reopening those classes with `class`
+    /// or `module` and defining methods with `def`, which mruby handles in
+    /// the VM without any hook to intercept it.
+    pub fn protect_core_classes(mut self, protect: bool) -> Self {
+        self.protect_core_classes = protect;
+        self
+    }
+
+    fn allows(&self, rule: &str) -> bool {
+        self.allow.iter().any(|allowed| allowed == rule)
+    }
+
+    pub(crate) fn denies(&self, rule: &str) -> bool {
+        self.deny.iter().any(|denied| denied == rule) && !self.allows(rule)
+    }
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        SandboxPolicy::new()
+            .deny("Kernel#eval")
+            .deny("Kernel.eval")
+            .deny("Kernel#exit")
+            .deny("Kernel.exit")
+            .deny("Kernel#exit!")
+            .deny("Kernel.exit!")
+            .deny("ObjectSpace")
+            .deny("File")
+            .deny("IO")
+            .deny("Dir")
+            .deny("Process")
+            .deny_string_eval("BasicObject#instance_eval")
+            .deny_string_eval("Module#class_eval")
+            .deny_string_eval("Module#module_eval")
+            .protect_core_classes(true)
+    }
+}
+
+enum Target<'a> {
+    Constant(&'a str),
+    Method(&'a str, &'a str),
+    ClassMethod(&'a str, &'a str),
+}
+
+fn parse(rule: &str) -> Target<'_> {
+    if let Some(idx) = rule.find('#') {
+        Target::Method(&rule[..idx], &rule[idx + 1..])
+    } else if let Some(idx) = rule.find('.') {
+        Target::ClassMethod(&rule[..idx], &rule[idx + 1..])
+    } else {
+        Target::Constant(rule)
+    }
+}
+
+impl<'mrb> Context<'mrb> {
+    pub(crate) fn class_get(&self, name: &str) -> MrbResult<'mrb, Option<MrbClass<'mrb>>> {
+        let name = CString::new(name).expect("CString::from");
+
+        let ptr = self.boundary(|| unsafe {
+            mrb_sys::mrbrs_class_get(self.mrb, name.as_ptr())
+        })?;
+
+        if ptr.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(unsafe { MrbClass(MrbPtr::new(self.mrb, ptr)) }))
+        }
+    }
+
+    pub(crate) fn apply_sandbox(&self, policy: &SandboxPolicy) -> MrbResult<'mrb, ()> {
+        self.load_string("class SecurityError < Exception; end")?;
+
+        // snapshot the core classes before we start removing constants
+        let core_classes = self.boundary(|| unsafe {
+            mrb_sys::mrbrs_sandbox_core_classes(self.mrb)
+        })?;
+
+        // the carriers aren't exceptions so raise refuses them anyway, but
+        // make sure no script can ever fake a panic or an abort
+        self.boundary(|| unsafe {
+            mrb_sys::mrbrs_sandbox_guard_raise(self.mrb);
+        })?;
+
+        // string evals are guarded before anything is removed so that the
+        // guards can find the original methods to wrap
+        for rule in &policy.deny_string_eval {
+            if policy.allows(rule) {
+                continue;
+            }
+
+            if let Target::Method(class, method) = parse(rule) {
+                if let Some(class) = self.class_get(class)? {
+                    let method = CString::new(method).expect("CString::from");
+
+                    self.boundary(|| unsafe {
+                        mrb_sys::mrbrs_sandbox_block_only(self.mrb, class.0.as_ptr(), method.as_ptr());
+                    })?;
+                }
+            }
+        }
+
+        for rule in &policy.deny {
+            if policy.allows(rule) {
+                continue;
+            }
+
+            match parse(rule) {
+                Target::Constant(name) => {
+                    let name = CString::new(name).expect("CString::from");
+                    let object = self.object_class();
+
+                    self.boundary(|| unsafe {
+                        mrb_sys::mrbrs_remove_const(self.mrb, object.0.as_ptr(), name.as_ptr());
+                    })?;
+                }
+                Target::Method(class, method) => {
+                    if let Some(class) = self.class_get(class)? {
+                        let method = CString::new(method).expect("CString::from");
+
+                        self.boundary(|| unsafe {
+                            mrb_sys::mrbrs_undef_method(self.mrb, class.0.as_ptr(), method.as_ptr());
+                        })?;
+                    }
+                }
+                Target::ClassMethod(class, method) => {
+                    if let Some(class) = self.class_get(class)? {
+                        let method = CString::new(method).expect("CString::from");
+
+                        self.boundary(|| unsafe {
+                            mrb_sys::mrbrs_undef_class_method(self.mrb, class.0.as_ptr(), method.as_ptr());
+                        })?;
+                    }
+                }
+            }
+        }
+
+        if policy.protect_core_classes {
+            self.boundary(|| unsafe {
+                mrb_sys::mrbrs_sandbox_protect_classes(self.mrb, core_classes);
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Context, MrbValue, SandboxPolicy};
+
+    fn eval(mrb: &Context, code: &str) -> Result<String, String> {
+        mrb.load_string(code)
+            .map(|val| mrb.inspect(val).to_string())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_sandboxed() {
+        let mut mrb = Mrb::sandboxed().unwrap();
+
+        mrb.context(|mrb| {
+            assert_eq!("2", eval(mrb, "instance_eval { 1 + 1 }").unwrap());
+            assert_eq!("evaluating strings is not allowed (SecurityError)", eval(mrb, "instance_eval('1 + 1')").unwrap_err());
+
+            assert_eq!("can't define methods on core classes (SecurityError)", eval(mrb, "String.define_method(:foo) { 1 }").unwrap_err());
+            assert_eq!("1", eval(mrb, "class Foo; define_method(:foo) { 1 }; end; Foo.new.foo").unwrap());
+
+            // reopening core classes isn't caught, see protect_core_classes
+            assert_eq!("1", eval(mrb, "class String; def foo; 1; end; end; 'x'.foo").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_raise_carrier() {
+        let mut mrb = Mrb::sandboxed().unwrap();
+
+        mrb.context(|mrb| {
+            // scripts have no way to reach the carriers, so hand them over
+            mrb.define_method(mrb.object_class(), "panic_carrier", |ctx, _self| {
+                Ok(unsafe {
+                    let ud = mrb_sys::mrbrs_get_ud(ctx.mrb);
+                    MrbValue::new(mrb_sys::mrbrs_obj_value((*ud).panic_carrier as *mut _))
+                })
+            }).unwrap();
+
+            mrb.define_method(mrb.object_class(), "timeout_carrier", |ctx, _self| {
+                Ok(unsafe {
+                    let ud = mrb_sys::mrbrs_get_ud(ctx.mrb);
+                    MrbValue::new(mrb_sys::mrbrs_obj_value((*ud).timeout_carrier as *mut _))
+                })
+            }).unwrap();
+
+            assert_eq!("can't raise internal exceptions (SecurityError)", eval(mrb, "raise panic_carrier").unwrap_err());
+            assert_eq!("can't raise internal exceptions (SecurityError)", eval(mrb, "Kernel.raise timeout_carrier").unwrap_err());
+            assert_eq!("boom (RuntimeError)", eval(mrb, "raise 'boom'").unwrap_err());
+        });
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let mut mrb = Mrb::builder()
+            .sandbox(SandboxPolicy::new().deny("Kernel#loop"))
+            .open()
+            .unwrap();
+
+        mrb.context(|mrb| {
+            assert!(eval(mrb, "loop { break }").unwrap_err().ends_with("(NoMethodError)"));
+        });
+
+        let policy = SandboxPolicy::default()
+            .deny("Kernel#loop")
+            .allow("Kernel#loop")
+            .allow("Kernel#eval")
+            .allow("Module#class_eval");
+
+        let mut mrb = Mrb::builder()
+            .sandbox(policy)
+            .open()
+            .unwrap();
+
+        mrb.context(|mrb| {
+            assert_eq!("nil", eval(mrb, "loop { break }").unwrap());
+            assert_eq!("2", eval(mrb, "eval('1 + 1')").unwrap());
+            assert_eq!("1", eval(mrb, "Object.class_eval('1')").unwrap());
+
+            // the rest of the default policy still applies
+            assert!(eval(mrb, "exit").unwrap_err().ends_with("(NoMethodError)"));
+        });
+    }
+}
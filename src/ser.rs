@@ -0,0 +1,440 @@
+use std::error;
+use std::fmt::{self, Display};
+
+use serde::ser::{self, Serialize};
+
+use crate::{Context, MrbResult};
+use crate::object::{MrbValue, MrbException};
+
+/// How struct fields, enum variants and string map keys are represented in
+/// the Hashes produced by `to_value`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyStyle {
+    Symbol,
+    String,
+}
+
+/// Converts `value` to a Ruby value, using symbol keys.
+pub fn to_value<'mrb, T: Serialize + ?Sized>(ctx: &Context<'mrb>, value: &T) -> MrbResult<'mrb, MrbValue<'mrb>> {
+    to_value_with(ctx, value, KeyStyle::Symbol)
+}
+
+/// Converts `value` to a Ruby value. Structs and maps become Hashes,
+/// sequences and tuples become Arrays, and enum variants are tagged with
+/// their name in the manner of `serde_json`. Unsupported values raise
+/// `TypeError`, and integers outside the Fixnum range raise `RangeError`.
+pub fn to_value_with<'mrb, T: Serialize + ?Sized>(ctx: &Context<'mrb>, value: &T, keys: KeyStyle) -> MrbResult<'mrb, MrbValue<'mrb>> {
+    value.serialize(Serializer { ctx, keys }).map_err(|err| match err {
+        Error::Exception(exc) => exc,
+        Error::Custom(message) => ctx.core_exception("TypeError", &message),
+    })
+}
+
+#[derive(Debug)]
+pub(crate) enum Error<'mrb> {
+    Exception(MrbException<'mrb>),
+    // raised by Serialize impls, which don't have a context to create an
+    // exception with
+    Custom(String),
+}
+
+impl<'mrb> From<MrbException<'mrb>> for Error<'mrb> {
+    fn from(exc: MrbException<'mrb>) -> Self {
+        Error::Exception(exc)
+    }
+}
+
+impl<'mrb> Display for Error<'mrb> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Exception(exc) => write!(f, "{:?}", exc),
+            Error::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl<'mrb> error::Error for Error<'mrb> {}
+
+impl<'mrb> ser::Error for Error<'mrb> {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Custom(msg.to_string())
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Serializer<'a, 'mrb> {
+    ctx: &'a Context<'mrb>,
+    keys: KeyStyle,
+}
+
+type Result<'mrb, T> = std::result::Result<T, Error<'mrb>>;
+
+impl<'a, 'mrb> Serializer<'a, 'mrb> {
+    fn key(&self, name: &str) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(match self.keys {
+            KeyStyle::Symbol => self.ctx.intern(name)?,
+            KeyStyle::String => self.ctx.new_string(name)?,
+        })
+    }
+
+    fn map_key(&self, key: MrbValue<'mrb>) -> Result<'mrb, MrbValue<'mrb>> {
+        match (self.keys, self.ctx.as_string(key)) {
+            (KeyStyle::Symbol, Some(name)) => Ok(self.ctx.intern(&name)?),
+            _ => Ok(key),
+        }
+    }
+
+    fn tagged(&self, variant: &str, value: MrbValue<'mrb>) -> Result<'mrb, MrbValue<'mrb>> {
+        let hash = self.ctx.new_hash()?;
+        self.ctx.hash_set(hash, self.key(variant)?, value)?;
+        Ok(hash)
+    }
+}
+
+impl<'a, 'mrb> ser::Serializer for Serializer<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    type SerializeSeq = Seq<'a, 'mrb>;
+    type SerializeTuple = Seq<'a, 'mrb>;
+    type SerializeTupleStruct = Seq<'a, 'mrb>;
+    type SerializeTupleVariant = Seq<'a, 'mrb>;
+    type SerializeMap = Map<'a, 'mrb>;
+    type SerializeStruct = Map<'a, 'mrb>;
+    type SerializeStructVariant = Map<'a, 'mrb>;
+
+    fn serialize_bool(self, v: bool) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.bool_value(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_int(v)?)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_int(v)?)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_int(v)?)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_int(v)?)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_float(v)?)
+    }
+
+    fn serialize_char(self, v: char) -> Result<'mrb, MrbValue<'mrb>> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_string(v)?)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.new_string_bytes(v)?)
+    }
+
+    fn serialize_none(self) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.nil_value())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<'mrb, MrbValue<'mrb>> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.nil_value())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<'mrb, MrbValue<'mrb>> {
+        Ok(self.ctx.nil_value())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<'mrb, MrbValue<'mrb>> {
+        self.key(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<'mrb, MrbValue<'mrb>> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T) -> Result<'mrb, MrbValue<'mrb>> {
+        let value = value.serialize(self)?;
+        self.tagged(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<'mrb, Seq<'a, 'mrb>> {
+        Ok(Seq { ser: self, array: self.ctx.new_array()?, variant: None })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<'mrb, Seq<'a, 'mrb>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<'mrb, Seq<'a, 'mrb>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<'mrb, Seq<'a, 'mrb>> {
+        Ok(Seq { ser: self, array: self.ctx.new_array()?, variant: Some(variant) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<'mrb, Map<'a, 'mrb>> {
+        Ok(Map { ser: self, hash: self.ctx.new_hash()?, key: None, variant: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<'mrb, Map<'a, 'mrb>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<'mrb, Map<'a, 'mrb>> {
+        Ok(Map { ser: self, hash: self.ctx.new_hash()?, key: None, variant: Some(variant) })
+    }
+}
+
+pub(crate) struct Seq<'a, 'mrb> {
+    ser: Serializer<'a, 'mrb>,
+    array: MrbValue<'mrb>,
+    variant: Option<&'static str>,
+}
+
+impl<'a, 'mrb> Seq<'a, 'mrb> {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        let value = value.serialize(self.ser)?;
+        Ok(self.ser.ctx.array_push(self.array, value)?)
+    }
+
+    fn finish(self) -> Result<'mrb, MrbValue<'mrb>> {
+        match self.variant {
+            Some(variant) => self.ser.tagged(variant, self.array),
+            None => Ok(self.array),
+        }
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeSeq for Seq<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeTuple for Seq<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeTupleStruct for Seq<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeTupleVariant for Seq<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+pub(crate) struct Map<'a, 'mrb> {
+    ser: Serializer<'a, 'mrb>,
+    hash: MrbValue<'mrb>,
+    key: Option<MrbValue<'mrb>>,
+    variant: Option<&'static str>,
+}
+
+impl<'a, 'mrb> Map<'a, 'mrb> {
+    fn field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<'mrb, ()> {
+        let key = self.ser.key(key)?;
+        let value = value.serialize(self.ser)?;
+        Ok(self.ser.ctx.hash_set(self.hash, key, value)?)
+    }
+
+    fn finish(self) -> Result<'mrb, MrbValue<'mrb>> {
+        match self.variant {
+            Some(variant) => self.ser.tagged(variant, self.hash),
+            None => Ok(self.hash),
+        }
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeMap for Map<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<'mrb, ()> {
+        let key = key.serialize(self.ser)?;
+        self.key = Some(self.ser.map_key(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<'mrb, ()> {
+        let key = self.key.take().expect("serialize_value called before serialize_key");
+        let value = value.serialize(self.ser)?;
+        Ok(self.ser.ctx.hash_set(self.hash, key, value)?)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeStruct for Map<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<'mrb, ()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+impl<'a, 'mrb> ser::SerializeStructVariant for Map<'a, 'mrb> {
+    type Ok = MrbValue<'mrb>;
+    type Error = Error<'mrb>;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<'mrb, ()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<'mrb, MrbValue<'mrb>> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use crate::{Mrb, Context, KeyStyle, to_value, to_value_with};
+
+    #[derive(Serialize)]
+    struct Config {
+        name: String,
+        port: u16,
+        ratio: f64,
+        tags: Vec<&'static str>,
+        parent: Option<Box<Config>>,
+        mode: Mode,
+        extra: BTreeMap<String, (i32, bool)>,
+    }
+
+    #[derive(Serialize)]
+    enum Mode {
+        Fast,
+        Limited(u32),
+        Custom { level: i8 },
+    }
+
+    fn inspect<T: Serialize>(mrb: &Context, value: &T, keys: KeyStyle) -> Result<String, String> {
+        to_value_with(mrb, value, keys)
+            .map(|val| mrb.inspect(val).to_string())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    #[test]
+    fn test_to_value() {
+        let mut mrb = Mrb::open();
+
+        let mut extra = BTreeMap::new();
+        extra.insert("a".to_owned(), (1, true));
+
+        let config = Config {
+            name: "web".to_owned(),
+            port: 8080,
+            ratio: 0.5,
+            tags: vec!["x", "y"],
+            parent: None,
+            mode: Mode::Fast,
+            extra,
+        };
+
+        mrb.context(|mrb| {
+            assert_eq!(
+                r#"{:name=>"web", :port=>8080, :ratio=>0.5, :tags=>["x", "y"], :parent=>nil, :mode=>:Fast, :extra=>{:a=>[1, true]}}"#,
+                inspect(mrb, &config, KeyStyle::Symbol).unwrap(),
+            );
+
+            assert_eq!(
+                r#"{"level"=>3}"#,
+                inspect(mrb, &vec![("level", 3)].into_iter().collect::<BTreeMap<_, _>>(), KeyStyle::String).unwrap(),
+            );
+
+            assert_eq!("{:Limited=>5}", inspect(mrb, &Mode::Limited(5), KeyStyle::Symbol).unwrap());
+            assert_eq!(r#"{"Custom"=>{"level"=>-1}}"#, inspect(mrb, &Mode::Custom { level: -1 }, KeyStyle::String).unwrap());
+
+            assert_eq!("integer 18446744073709551615 out of Fixnum range (RangeError)", inspect(mrb, &u64::MAX, KeyStyle::Symbol).unwrap_err());
+
+            // the result is an ordinary value that scripts can use
+            let value = to_value(mrb, &config).unwrap();
+            mrb.define_global_const("CONFIG", value).unwrap();
+            assert_eq!("8081", mrb.inspect(mrb.load_string("CONFIG[:port] + 1").unwrap()));
+        });
+    }
+}
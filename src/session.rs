@@ -0,0 +1,148 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+
+use mrb_sys as sys;
+
+use crate::{Mrb, Context, Error, MrbResult};
+use crate::compile::{Diagnostic, Parser};
+use crate::object::MrbValue;
+
+/// Whether a chunk of input is ready to be evaluated by a `Session`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Complete,
+    /// The input stops partway through an expression, string or heredoc,
+    /// so more lines are needed before it can be run.
+    Incomplete,
+    /// The input is a syntax error no matter what follows it.
+    Invalid(Vec<Diagnostic>),
+}
+
+/// A series of evaluations which share top level local variables, in the
+/// manner of an interactive shell.
+///
+/// The locals live on the interpreter's stack, which any other top level
+/// evaluation resets. The session therefore holds on to the `Mrb` for as
+/// long as it is open.
+pub struct Session<'a> {
+    mrb: &'a mut Mrb,
+    cxt: *mut sys::mrbc_context,
+}
+
+impl Mrb {
+    pub fn session(&mut self, filename: &str) -> Result<Session<'_>, Error> {
+        let filename = CString::new(filename).expect("CString::from");
+
+        let cxt = self.try_context(|ctx| {
+            ctx.boundary(|| unsafe {
+                sys::mrbrs_session_new(ctx.mrb, filename.as_ptr())
+            })
+        })?;
+
+        Ok(Session { mrb: self, cxt })
+    }
+}
+
+impl<'a> Session<'a> {
+    /// Parses `code` against the session's local variables without running
+    /// it or declaring any new ones.
+    pub fn check(&mut self, code: &str) -> Result<Input, Error> {
+        let cxt = self.cxt;
+
+        self.mrb.try_context(|ctx| {
+            let ptr = ctx.boundary(|| unsafe {
+                sys::mrbrs_session_parse(
+                    ctx.mrb,
+                    cxt,
+                    code.as_ptr() as *const i8,
+                    code.len().try_into().unwrap(),
+                )
+            })?;
+
+            let parser = unsafe { Parser::new(ptr) };
+
+            Ok(if parser.is_incomplete() {
+                Input::Incomplete
+            } else if parser.has_errors() {
+                Input::Invalid(parser.diagnostics())
+            } else {
+                Input::Complete
+            })
+        })
+    }
+
+    /// Runs `code`, passing its result to `f`. Local variables assigned at
+    /// the top level remain visible to later calls, even if `code` raises.
+    /// Loading code through the context given to `f` would wipe them.
+    pub fn eval<Ret>(&mut self, code: &str, f: impl for<'mrb> FnOnce(&Context<'mrb>, MrbResult<'mrb, MrbValue<'mrb>>) -> Ret) -> Ret {
+        let cxt = self.cxt;
+
+        self.mrb.context(|ctx| {
+            let result = ctx.boundary(|| unsafe {
+                sys::mrbrs_session_load(
+                    ctx.mrb,
+                    cxt,
+                    code.as_ptr() as *const i8,
+                    code.len().try_into().unwrap(),
+                )
+            }).map(|value| unsafe { MrbValue::new(value) });
+
+            f(ctx, result)
+        })
+    }
+}
+
+impl<'a> Drop for Session<'a> {
+    fn drop(&mut self) {
+        unsafe { sys::mrbc_context_free(self.mrb.state.as_ptr(), self.cxt) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Input, Session};
+
+    fn eval(session: &mut Session, code: &str) -> Result<String, String> {
+        session.eval(code, |mrb, result| {
+            result
+                .map(|val| mrb.inspect(val).to_string())
+                .map_err(|err| format!("{:?}", err))
+        })
+    }
+
+    #[test]
+    fn test_session_locals() {
+        let mut mrb = Mrb::open();
+        let mut session = mrb.session("(session)").unwrap();
+
+        assert_eq!("1", eval(&mut session, "x = 1").unwrap());
+        assert_eq!("2", eval(&mut session, "x + 1").unwrap());
+
+        assert!(eval(&mut session, "y = 2; raise 'hello'").is_err());
+        assert_eq!("3", eval(&mut session, "x + y").unwrap());
+
+        assert!(eval(&mut session, "x = (").unwrap_err().ends_with("(SyntaxError)"));
+        assert_eq!("1", eval(&mut session, "x").unwrap());
+    }
+
+    #[test]
+    fn test_session_check() {
+        let mut mrb = Mrb::open();
+        let mut session = mrb.session("(session)").unwrap();
+
+        assert_eq!(Input::Complete, session.check("1 + 1").unwrap());
+        assert_eq!(Input::Incomplete, session.check("def foo").unwrap());
+        assert_eq!(Input::Incomplete, session.check("[1,\n2,").unwrap());
+        assert_eq!(Input::Incomplete, session.check("'hello").unwrap());
+        assert_eq!(Input::Incomplete, session.check("<<EOS\nhello\n").unwrap());
+
+        match session.check("end").unwrap() {
+            Input::Invalid(diagnostics) => assert!(!diagnostics.is_empty()),
+            input => panic!("expected invalid input, got {:?}", input),
+        }
+
+        // checking doesn't declare the locals it sees
+        assert_eq!(Input::Complete, session.check("z = 1").unwrap());
+        assert!(eval(&mut session, "z").unwrap_err().ends_with("(NameError)"));
+    }
+}
@@ -1,30 +1,92 @@
+use std::os::raw::c_void;
 use std::ptr;
 use mrb_sys as sys;
 
-pub(crate) struct MrbState(*mut sys::mrb_state);
+use crate::alloc::{self, Allocator};
+use crate::limits::Limits;
+use crate::symbol::SymbolCache;
+
+pub(crate) struct MrbState {
+    mrb: *mut sys::mrb_state,
+
+    // mruby calls back into the allocator right up until mrb_close returns,
+    // so this must be dropped after the state is closed
+    allocator: Box<Allocator>,
+
+    // referenced from the mrbrs_ud struct so the code fetch hook can find it
+    limits: Box<Limits>,
+
+    // referenced from the mrbrs_ud struct so any context can find it
+    _symbols: Box<SymbolCache>,
+}
 
 impl MrbState {
-    pub fn open() -> Result<Self, ()> {
+    pub fn open(allocator: Allocator, limits: Limits) -> Result<Self, ()> {
+        let allocator = Box::new(allocator);
+        let limits = Box::new(limits);
+        let symbols = Box::new(SymbolCache::default());
+
         let state = unsafe {
-            sys::mrbrs_open_core()
+            sys::mrbrs_open_core(
+                Some(alloc::allocf),
+                &*allocator as *const Allocator as *mut c_void,
+            )
         };
 
         if state == ptr::null_mut() {
-            Err(())
-        } else {
-            Ok(MrbState(state))
+            return Err(());
+        }
+
+        if !allocator.enforce_limit() {
+            unsafe { sys::mrbrs_close(state) };
+            return Err(());
+        }
+
+        unsafe {
+            let ud = sys::mrbrs_get_ud(state);
+            (*ud).limits = &*limits as *const Limits as *mut c_void;
+            (*ud).symbols = &*symbols as *const SymbolCache as *mut c_void;
         }
+
+        Ok(MrbState { mrb: state, allocator, limits, _symbols: symbols })
     }
 
     pub fn as_ptr(&self) -> *mut sys::mrb_state {
-        self.0
+        self.mrb
+    }
+
+    pub fn allocator(&self) -> &Allocator {
+        &self.allocator
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
     }
 }
 
 impl Drop for MrbState {
     fn drop(&mut self) {
         unsafe {
-            sys::mrbrs_close(self.0);
+            sys::mrbrs_close(self.mrb);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::mem::MaybeUninit;
+    use std::ptr;
+    use mrb_sys as sys;
+
+    #[test]
+    fn test_state_layout() {
+        // fields up to kernel_module are read straight from the bindings,
+        // so they must line up with the mruby we're linked against
+        let state = MaybeUninit::<sys::mrb_state>::uninit();
+        let base = state.as_ptr();
+        let end = unsafe { ptr::addr_of!((*base).kernel_module).add(1) };
+
+        let prefix_size = end as usize - base as usize;
+        assert_eq!(unsafe { sys::mrbrs_state_prefix_size() } as usize, prefix_size);
+    }
+}
@@ -0,0 +1,169 @@
+use std::convert::TryInto;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult};
+use crate::object::MrbValue;
+
+// the functions modifying Strings raise FrozenError for frozen Strings and
+// TypeError for other values. they may reallocate the String's buffer, which
+// is why `MrbValue::as_bytes` is unsafe. Strings from `new_string_static` are
+// copied the first time they're modified, so the static data is never written
+// to
+impl<'mrb> Context<'mrb> {
+    /// Creates an empty String with room for `capacity` bytes, to be filled
+    /// with `str_cat` and `str_append` without reallocating.
+    pub fn str_buf_new(&self, capacity: usize) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_str_buf_new(self.mrb, capacity.try_into().unwrap())
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    /// Appends `bytes` to `string`.
+    pub fn str_cat(&self, string: MrbValue<'mrb>, bytes: &[u8]) -> MrbResult<'mrb, ()> {
+        self.boundary(|| unsafe {
+            sys::mrbrs_str_cat(
+                self.mrb,
+                string.as_raw(),
+                bytes.as_ptr() as *const i8,
+                bytes.len().try_into().unwrap(),
+            )
+        })
+    }
+
+    /// Appends the String `other` to `string`.
+    pub fn str_append(&self, string: MrbValue<'mrb>, other: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        self.boundary(|| unsafe {
+            sys::mrbrs_str_append(self.mrb, string.as_raw(), other.as_raw())
+        })
+    }
+
+    /// Truncates `string` to `len` bytes, or pads it to `len` bytes with
+    /// zeros.
+    pub fn str_resize(&self, string: MrbValue<'mrb>, len: usize) -> MrbResult<'mrb, ()> {
+        self.boundary(|| unsafe {
+            sys::mrbrs_str_resize(self.mrb, string.as_raw(), len.try_into().unwrap())
+        })
+    }
+
+    /// A new String holding part of `string`, like `string[start, len]` in
+    /// Ruby. A negative `start` counts back from the end, and None is
+    /// returned when `start` is out of range.
+    pub fn str_substr(&self, string: MrbValue<'mrb>, start: i64, len: i64) -> MrbResult<'mrb, Option<MrbValue<'mrb>>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_str_substr(self.mrb, string.as_raw(), start, len)
+        })?;
+
+        let result = unsafe { MrbValue::new(result) };
+        Ok(Some(result).filter(|result| result.as_raw().tt == sys::mrb_vtype_MRB_TT_STRING))
+    }
+
+    /// An unfrozen copy of `string`.
+    pub fn str_dup(&self, string: MrbValue<'mrb>) -> MrbResult<'mrb, MrbValue<'mrb>> {
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_str_dup(self.mrb, string.as_raw())
+        })?;
+
+        Ok(unsafe { MrbValue::new(result) })
+    }
+
+    /// Freezes `value`, so it can no longer be modified.
+    pub fn freeze(&self, value: MrbValue<'mrb>) -> MrbResult<'mrb, ()> {
+        self.boundary(|| unsafe {
+            sys::mrbrs_obj_freeze(self.mrb, value.as_raw())
+        })
+    }
+
+    /// Whether `value` is frozen. Immediate values such as nil, numbers and
+    /// Symbols are always frozen.
+    pub fn is_frozen(&self, value: MrbValue<'mrb>) -> bool {
+        unsafe { sys::mrbrs_frozen_p(value.as_raw()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, Context, MrbValue};
+
+    fn text<'mrb>(mrb: &Context<'mrb>, value: MrbValue<'mrb>) -> Option<String> {
+        mrb.string_bytes(value).map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_str_buf() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let buf = mrb.str_buf_new(64).unwrap();
+            assert_eq!(Some(""), text(mrb, buf).as_deref());
+
+            mrb.str_cat(buf, b"hello").unwrap();
+            mrb.str_append(buf, mrb.new_string(", world").unwrap()).unwrap();
+            assert_eq!(Some("hello, world"), text(mrb, buf).as_deref());
+
+            mrb.str_resize(buf, 5).unwrap();
+            assert_eq!(Some("hello"), text(mrb, buf).as_deref());
+            mrb.str_resize(buf, 7).unwrap();
+            assert_eq!(Some(b"hello\0\0".to_vec()), mrb.string_bytes(buf));
+
+            let sub = mrb.str_substr(buf, 1, 3).unwrap().unwrap();
+            assert_eq!(Some("ell"), text(mrb, sub).as_deref());
+            assert_eq!(Some("lo"), text(mrb, mrb.str_substr(buf, -4, 2).unwrap().unwrap()).as_deref());
+            assert!(mrb.str_substr(buf, 20, 1).unwrap().is_none());
+
+            let err = mrb.str_append(buf, mrb.nil_value()).unwrap_err();
+            assert_eq!("expected String (TypeError)", format!("{:?}", err));
+            let err = mrb.str_cat(mrb.nil_value(), b"x").unwrap_err();
+            assert_eq!("expected String (TypeError)", format!("{:?}", err));
+        });
+    }
+
+    #[test]
+    fn test_str_realloc() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let s = mrb.new_string("abc").unwrap();
+            let before = mrb.string_bytes(s).unwrap();
+
+            // grows well past the original buffer, moving the contents
+            mrb.str_cat(s, &[b'x'; 4096]).unwrap();
+
+            assert_eq!(b"abc".to_vec(), before);
+            assert_eq!(4099, mrb.string_bytes(s).unwrap().len());
+        });
+    }
+
+    #[test]
+    fn test_str_frozen() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let s = mrb.new_string("abc").unwrap();
+            assert!(!mrb.is_frozen(s));
+            assert!(mrb.is_frozen(mrb.nil_value()));
+
+            mrb.freeze(s).unwrap();
+            assert!(mrb.is_frozen(s));
+
+            let err = mrb.str_cat(s, b"d").unwrap_err();
+            assert!(format!("{:?}", err).ends_with("(FrozenError)"));
+            assert_eq!(Some("abc"), text(mrb, s).as_deref());
+
+            let copy = mrb.str_dup(s).unwrap();
+            assert!(!mrb.is_frozen(copy));
+            mrb.str_cat(copy, b"d").unwrap();
+            assert_eq!(Some("abcd"), text(mrb, copy).as_deref());
+
+            // static strings get a buffer of their own before being modified
+            static TEXT: &str = "static";
+            let s = mrb.new_string_static(TEXT).unwrap();
+            mrb.str_cat(s, b" text").unwrap();
+            mrb.str_resize(s, 3).unwrap();
+            assert_eq!(Some("sta"), text(mrb, s).as_deref());
+            assert_eq!("static", TEXT);
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::slice;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult};
+use crate::object::{MrbValue, MrbSymbol};
+
+// the symbols already interned in a state, by name. mruby never frees
+// symbols, so entries can't go stale and the cache grows no larger than the
+// symbol table itself
+#[derive(Default)]
+pub(crate) struct SymbolCache(RefCell<HashMap<Vec<u8>, sys::mrb_sym>>);
+
+unsafe fn cache<'a>(mrb: *mut sys::mrb_state) -> Option<&'a SymbolCache> {
+    let ud = sys::mrbrs_get_ud(mrb);
+    ((*ud).symbols as *const SymbolCache).as_ref()
+}
+
+impl<'mrb> Context<'mrb> {
+    /// Interns `name` as a Symbol. Names are cached per state, so interning
+    /// the same name again is a hash lookup.
+    pub fn symbol(&self, name: &str) -> MrbResult<'mrb, MrbSymbol<'mrb>> {
+        self.symbol_from_bytes(name.as_bytes())
+    }
+
+    pub(crate) fn symbol_from_bytes(&self, name: &[u8]) -> MrbResult<'mrb, MrbSymbol<'mrb>> {
+        let cache = unsafe { cache(self.mrb) };
+
+        if let Some(sym) = cache.and_then(|cache| cache.0.borrow().get(name).copied()) {
+            return Ok(unsafe { MrbSymbol::new(sym) });
+        }
+
+        let result = self.boundary(|| unsafe {
+            sys::mrbrs_intern(
+                self.mrb,
+                name.as_ptr() as *const i8,
+                name.len().try_into().unwrap(),
+            )
+        })?;
+
+        let sym = unsafe { MrbValue::new(result) }.as_symbol().expect("Symbol");
+
+        if let Some(cache) = cache {
+            cache.0.borrow_mut().insert(name.to_vec(), sym.as_raw());
+        }
+
+        Ok(sym)
+    }
+
+    /// The name of `sym`, which needn't be valid UTF-8.
+    pub fn symbol_bytes(&self, sym: MrbSymbol<'mrb>) -> Vec<u8> {
+        unsafe {
+            let mut len: sys::mrb_int = 0;
+            let ptr = sys::mrb_sym_name_len(self.mrb, sym.as_raw(), &mut len as *mut _);
+
+            // short names are packed into the symbol itself and unpacked into
+            // a buffer shared by the whole state, so they must be copied out
+            // before the next lookup
+            slice::from_raw_parts(ptr as *const u8, len.try_into().unwrap()).to_vec()
+        }
+    }
+
+    /// The name of `sym`, with invalid UTF-8 replaced.
+    pub fn symbol_name(&self, sym: MrbSymbol<'mrb>) -> String {
+        match String::from_utf8(self.symbol_bytes(sym)) {
+            Ok(name) => name,
+            Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mrb, MrbValue};
+
+    #[test]
+    fn test_symbol_name() {
+        let mut mrb = Mrb::open();
+
+        mrb.context(|mrb| {
+            let sym = mrb.symbol("hello").unwrap();
+            assert_eq!(sym, mrb.symbol("hello").unwrap());
+            assert_ne!(sym, mrb.symbol("world").unwrap());
+            assert_eq!("hello", mrb.symbol_name(sym));
+
+            // symbols interned by Ruby code are the same ones
+            let value = mrb.load_string(":hello").unwrap();
+            assert_eq!(Some(sym), value.as_symbol());
+            assert_eq!(":hello", mrb.inspect(MrbValue::from(sym)));
+            assert_eq!(None, mrb.new_string("hello").unwrap().as_symbol());
+
+            let value = mrb.load_string(r#":"caf\xff""#).unwrap();
+            assert_eq!(b"caf\xff".to_vec(), mrb.symbol_bytes(value.as_symbol().unwrap()));
+            assert_eq!("caf\u{fffd}", mrb.symbol_name(value.as_symbol().unwrap()));
+
+            // short names share one buffer inside mruby, so both must stay
+            // intact while held together
+            let a = mrb.symbol_bytes(mrb.symbol("a").unwrap());
+            let b = mrb.symbol_bytes(mrb.symbol("bc").unwrap());
+            assert_eq!((&b"a"[..], &b"bc"[..]), (&a[..], &b[..]));
+        });
+
+        // the cache belongs to the state, so outlives each context
+        mrb.context(|mrb| {
+            let value = mrb.load_string(":hello").unwrap();
+            assert_eq!(value.as_symbol(), Some(mrb.symbol("hello").unwrap()));
+        });
+    }
+}
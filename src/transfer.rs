@@ -0,0 +1,113 @@
+use std::os::raw::c_void;
+
+use mrb_sys as sys;
+
+use crate::{Context, MrbResult, MAX_DEPTH};
+use crate::object::MrbValue;
+
+impl<'mrb> Context<'mrb> {
+    /// Deep copies `value` into the state of `to`, which is usually another
+    /// `Mrb` instance. Only plain data is copied: nil, booleans, numbers,
+    /// Strings, Symbols, Arrays and Hashes. Other values raise `TypeError`
+    /// in `to`, and cyclic or too deeply nested structures raise
+    /// `ArgumentError`. Hash defaults and frozen flags aren't copied.
+    pub fn transfer<'to>(&self, to: &Context<'to>, value: MrbValue<'mrb>) -> MrbResult<'to, MrbValue<'to>> {
+        self.transfer_value(to, value, &mut Vec::new())
+    }
+
+    // `parents` holds the Arrays and Hashes enclosing `value`
+    fn transfer_value<'to>(&self, to: &Context<'to>, value: MrbValue<'mrb>, parents: &mut Vec<*mut c_void>) -> MrbResult<'to, MrbValue<'to>> {
+        let raw = value.as_raw();
+
+        match raw.tt {
+            sys::mrb_vtype_MRB_TT_FALSE if value.is_nil() => Ok(to.nil_value()),
+            sys::mrb_vtype_MRB_TT_FALSE => Ok(to.bool_value(false)),
+            sys::mrb_vtype_MRB_TT_TRUE => Ok(to.bool_value(true)),
+            sys::mrb_vtype_MRB_TT_FIXNUM => Ok(to.fixnum_value(value.as_i64().expect("Fixnum"))),
+            sys::mrb_vtype_MRB_TT_FLOAT => to.new_float(value.as_f64().expect("Float")),
+            sys::mrb_vtype_MRB_TT_SYMBOL => to.intern_bytes(&self.symbol_bytes(value.as_symbol().expect("Symbol"))),
+            sys::mrb_vtype_MRB_TT_STRING => to.new_string_bytes(&self.string_bytes(value).expect("String")),
+            sys::mrb_vtype_MRB_TT_ARRAY | sys::mrb_vtype_MRB_TT_HASH => {
+                let ptr = value.as_ptr();
+
+                if parents.contains(&ptr) {
+                    return Err(to.core_exception("ArgumentError", "circular reference detected in transfer"));
+                }
+
+                if parents.len() == MAX_DEPTH {
+                    return Err(to.core_exception("ArgumentError", &format!("nesting of {} is too deep to transfer", MAX_DEPTH + 1)));
+                }
+
+                parents.push(ptr);
+                let result = self.transfer_container(to, value, parents);
+                parents.pop();
+                result
+            }
+            _ => Err(to.core_exception("TypeError", &format!("can't transfer {} between states", self.class_name(value)))),
+        }
+    }
+
+    fn transfer_container<'to>(&self, to: &Context<'to>, value: MrbValue<'mrb>, parents: &mut Vec<*mut c_void>) -> MrbResult<'to, MrbValue<'to>> {
+        if let Some(values) = self.array_entries(value) {
+            let array = to.new_array()?;
+
+            for value in values {
+                to.array_push(array, self.transfer_value(to, value, parents)?)?;
+            }
+
+            return Ok(array);
+        }
+
+        // exceptions belong to the state they were raised in, so are
+        // reported again in `to`
+        let entries = self.hash_entries(value)
+            .map_err(|exc| to.core_exception("RuntimeError", &format!("{:?}", exc)))?;
+
+        let hash = to.new_hash()?;
+
+        for (key, value) in entries {
+            let key = self.transfer_value(to, key, parents)?;
+            let value = self.transfer_value(to, value, parents)?;
+            to.hash_set(hash, key, value)?;
+        }
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Mrb;
+
+    #[test]
+    fn test_transfer() {
+        let mut from = Mrb::open();
+        let mut to = Mrb::open();
+
+        from.context(|from| {
+            to.context(|to| {
+                let transfer = |code: &str| {
+                    let value = from.load_string(code).unwrap();
+                    from.transfer(to, value).map_err(|err| format!("{:?}", err))
+                };
+
+                let code = r#"{ name: "tenant", "ids" => [1, 2.5, nil, true, false], nested: { :"odd sym" => "\xff" } }"#;
+                let value = transfer(code).unwrap();
+                assert_eq!(from.inspect(from.load_string(code).unwrap()), to.inspect(value));
+
+                // the copy is independent of the original
+                to.global_set("$copy", value).unwrap();
+                to.load_string("$copy[:name] << '!'").unwrap();
+                assert_eq!("\"tenant!\"", to.inspect(to.load_string("$copy[:name]").unwrap()));
+
+                // shared values are copied, but cycles are rejected
+                assert_eq!("[[1], [1]]", to.inspect(transfer("a = [1]; [a, a]").unwrap()));
+                assert_eq!("circular reference detected in transfer (ArgumentError)", transfer("a = []; a << { a: a }; a").unwrap_err());
+                assert_eq!("nesting of 129 is too deep to transfer (ArgumentError)", transfer("a = []; 200.times { a = [a] }; a").unwrap_err());
+
+                assert_eq!("can't transfer Proc between states (TypeError)", transfer("[proc { 1 }]").unwrap_err());
+                assert_eq!("can't transfer Object between states (TypeError)", transfer("{ obj: Object.new }").unwrap_err());
+            });
+        });
+    }
+}